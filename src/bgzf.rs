@@ -0,0 +1,356 @@
+use crate::error::{Context, Error, Result};
+use crate::extra_field::{KnownSubfield, Subfields, BGZF_ID};
+use crate::producer::inflate_member;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// ID1, ID2, CM, FLG, MTIME, XFL, OS, XLEN
+const FIXED_HEADER_SIZE: usize = 12;
+const FEXTRA: u8 = 4;
+
+/// Read the compressed bytes of the next BGZF block into buf
+/// Returns false if there is no more block
+pub fn read_block(mut read: impl Read, buf: &mut Vec<u8>) -> Result<bool> {
+    buf.clear();
+    buf.resize(FIXED_HEADER_SIZE, 0);
+    let mut n = 0;
+    while n < FIXED_HEADER_SIZE {
+        match read.read(&mut buf[n..]) {
+            Ok(0) if n == 0 => return Ok(false),
+            Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+            Ok(m) => n += m,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if buf[3] & FEXTRA == 0 {
+        return Err(Error::InvalidBgzfBlock);
+    }
+
+    let xlen = u16::from_le_bytes([buf[10], buf[11]]) as usize;
+    buf.resize(FIXED_HEADER_SIZE + xlen, 0);
+    read.read_exact(&mut buf[FIXED_HEADER_SIZE..])?;
+    let subfield = Subfields::new(&buf[FIXED_HEADER_SIZE..])
+        .find_id(BGZF_ID)?
+        .ok_or(Error::InvalidBgzfBlock)?;
+    let block_size = match subfield.decode()? {
+        KnownSubfield::Bgzf { block_size } => block_size as usize,
+        _ => unreachable!(),
+    };
+    if block_size < buf.len() {
+        return Err(Error::InvalidBgzfBlock);
+    }
+
+    let n = buf.len();
+    buf.resize(block_size, 0);
+    read.read_exact(&mut buf[n..])?;
+    Ok(true)
+}
+
+/// Inflate a BGZF block read by `read_block`, appending the data to out
+/// The context of an error is relative to the block
+pub fn inflate_block(block: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let begin = out.len();
+    let len = inflate_member(block, out)?;
+    if len != block.len() {
+        // the member ends short of the block
+        let context = Context {
+            member: 1,
+            bit_position: Some(len as u64 * 8),
+            output_position: (out.len() - begin) as u64,
+            block: None,
+        };
+        return Err(Error::InvalidBgzfBlock.with_context(context));
+    }
+    Ok(())
+}
+
+/// attach the context of the whole input to an error of a block,
+/// given the 1-based block index and the positions at which the block begins
+/// the errors reading the block itself point at its beginning
+fn rebase(e: Error, block_idx: usize, block_offset: u64, output_offset: u64) -> Error {
+    let e = match e.context() {
+        Some(_) => e,
+        None => e.with_context(Context {
+            bit_position: Some(0),
+            ..Default::default()
+        }),
+    };
+    e.rebase(block_idx, block_offset * 8, output_offset)
+}
+
+/// Reads BGZF, i.e., blocked gzip, one block at a time
+/// Positions are expressed as virtual offsets, i.e.,
+/// the compressed offset of the block << 16 | the uncompressed offset within the block
+/// The block index and output position in the context of an error
+/// count from the block last sought, if any
+pub struct BgzfReader<R: Read> {
+    read: R,
+    block: Vec<u8>, // compressed bytes of the current block
+    buf: Vec<u8>,   // uncompressed data of the current block
+    begin: usize,
+    block_offset: u64,      // compressed offset of the current block
+    next_block_offset: u64, // compressed offset of the next block
+    num_blocks: usize,      // # blocks loaded so far
+    output_offset: u64,     // # uncompressed bytes before the current block
+}
+
+impl<R: Read> BgzfReader<R> {
+    /// read should be positioned at the beginning of the BGZF file
+    pub fn new(read: R) -> Self {
+        Self {
+            read,
+            block: Vec::new(),
+            buf: Vec::new(),
+            begin: 0,
+            block_offset: 0,
+            next_block_offset: 0,
+            num_blocks: 0,
+            output_offset: 0,
+        }
+    }
+
+    /// virtual offset of the next byte to be read
+    pub fn virtual_offset(&self) -> u64 {
+        if self.begin < self.buf.len() {
+            self.block_offset << 16 | self.begin as u64
+        } else {
+            self.next_block_offset << 16
+        }
+    }
+
+    /// load the next non-empty block
+    /// returns false at the end of the input
+    fn next_block(&mut self) -> Result<bool> {
+        while self.load_block()? {
+            if !self.buf.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// load the next block, which may be empty
+    /// returns false at the end of the input
+    fn load_block(&mut self) -> Result<bool> {
+        self.output_offset += self.buf.len() as u64;
+        self.buf.clear();
+        self.begin = 0;
+        self.block_offset = self.next_block_offset;
+        self.num_blocks += 1;
+        let block_idx = self.num_blocks;
+        let rebase = |e| rebase(e, block_idx, self.block_offset, self.output_offset);
+        if !read_block(&mut self.read, &mut self.block).map_err(rebase)? {
+            return Ok(false);
+        }
+        self.next_block_offset += self.block.len() as u64;
+        inflate_block(&self.block, &mut self.buf).map_err(rebase)?;
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// seek to the virtual offset, as returned by `virtual_offset()` or found in an index
+    pub fn seek_virtual(&mut self, virtual_offset: u64) -> Result<()> {
+        let block_offset = virtual_offset >> 16;
+        let begin = (virtual_offset & 0xFFFF) as usize;
+        if block_offset != self.block_offset || self.buf.is_empty() {
+            self.read.seek(SeekFrom::Start(block_offset))?;
+            self.next_block_offset = block_offset;
+            self.buf.clear();
+            self.num_blocks = 0;
+            self.output_offset = 0;
+            self.load_block()?;
+        }
+        if begin > self.buf.len() {
+            return Err(Error::InvalidVirtualOffset);
+        }
+        self.begin = begin;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.fill_buf()?.len());
+        buf[..n].copy_from_slice(&self.buf[self.begin..self.begin + n]);
+        self.begin += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.begin == self.buf.len() {
+            self.next_block()?;
+        }
+        Ok(&self.buf[self.begin..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.begin += amt;
+    }
+}
+
+/// # blocks dispatched to the workers but not yet consumed, per worker
+const IN_FLIGHT_PER_THREAD: usize = 4;
+
+type Job = (usize, u64, Vec<u8>); // block index, compressed offset, compressed block
+type Inflated = (usize, u64, Result<Vec<u8>>); // block index, compressed offset, uncompressed data
+
+/// Reads BGZF, inflating the blocks in parallel on worker threads
+/// The input is read and split into blocks on the calling thread,
+/// while the blocks are inflated and verified on the workers and reassembled in order
+pub struct ParallelBgzfReader<R: Read> {
+    read: R,
+    job_tx: Option<SyncSender<Job>>,
+    inflated_rx: Receiver<Inflated>,
+    workers: Vec<JoinHandle<()>>,
+    inflated: BTreeMap<usize, (u64, Result<Vec<u8>>)>, // received out of order
+    max_in_flight: usize,
+    num_dispatched: usize,
+    num_consumed: usize,
+    block_offset: u64,  // compressed offset of the next block to dispatch
+    output_offset: u64, // # uncompressed bytes of the blocks consumed so far
+    eof: bool,
+    buf: Vec<u8>,
+    begin: usize,
+}
+
+impl<R: Read> ParallelBgzfReader<R> {
+    pub fn new(read: R, num_threads: usize) -> Self {
+        let num_threads = num_threads.max(1);
+        let max_in_flight = num_threads * IN_FLIGHT_PER_THREAD;
+        let (job_tx, job_rx) = std::sync::mpsc::sync_channel::<Job>(max_in_flight);
+        let (inflated_tx, inflated_rx) = std::sync::mpsc::channel::<Inflated>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let workers = (0..num_threads)
+            .map(|_| {
+                let job_rx = job_rx.clone();
+                let inflated_tx = inflated_tx.clone();
+                std::thread::spawn(move || inflate_blocks(job_rx, inflated_tx))
+            })
+            .collect();
+
+        Self {
+            read,
+            job_tx: Some(job_tx),
+            inflated_rx,
+            workers,
+            inflated: BTreeMap::new(),
+            max_in_flight,
+            num_dispatched: 0,
+            num_consumed: 0,
+            block_offset: 0,
+            output_offset: 0,
+            eof: false,
+            buf: Vec::new(),
+            begin: 0,
+        }
+    }
+
+    /// read blocks and hand them over to the workers until enough are in flight
+    fn dispatch(&mut self) -> Result<()> {
+        while !self.eof && self.num_dispatched - self.num_consumed < self.max_in_flight {
+            let mut block = Vec::new();
+            match read_block(&mut self.read, &mut block) {
+                Ok(true) => {
+                    let block_offset = self.block_offset;
+                    self.block_offset += block.len() as u64;
+                    let job_tx = self.job_tx.as_ref().unwrap();
+                    job_tx
+                        .send((self.num_dispatched, block_offset, block))
+                        .map_err(|_| std::io::Error::other("worker thread panicked"))?;
+                }
+                Ok(false) => {
+                    self.eof = true;
+                    break;
+                }
+                Err(e) => {
+                    // deliver the error in order, after all the blocks before it
+                    let inflated = (self.block_offset, Err(e));
+                    self.inflated.insert(self.num_dispatched, inflated);
+                    self.eof = true;
+                }
+            }
+            self.num_dispatched += 1;
+        }
+        Ok(())
+    }
+
+    /// load the next non-empty block
+    /// returns false at the end of the input
+    fn next_block(&mut self) -> Result<bool> {
+        self.begin = 0;
+        loop {
+            self.dispatch()?;
+            if self.num_consumed == self.num_dispatched {
+                self.buf.clear();
+                return Ok(false);
+            }
+            while !self.inflated.contains_key(&self.num_consumed) {
+                let (idx, block_offset, inflated) = self
+                    .inflated_rx
+                    .recv()
+                    .map_err(|_| std::io::Error::other("worker thread panicked"))?;
+                self.inflated.insert(idx, (block_offset, inflated));
+            }
+            let (block_offset, inflated) = self.inflated.remove(&self.num_consumed).unwrap();
+            self.num_consumed += 1;
+            self.output_offset += std::mem::take(&mut self.buf).len() as u64;
+            self.buf = inflated
+                .map_err(|e| rebase(e, self.num_consumed, block_offset, self.output_offset))?;
+            if !self.buf.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+fn inflate_blocks(job_rx: Arc<Mutex<Receiver<Job>>>, inflated_tx: Sender<Inflated>) {
+    loop {
+        let job = job_rx.lock().unwrap().recv();
+        let Ok((idx, block_offset, block)) = job else {
+            return; // no more block
+        };
+        let mut buf = Vec::new();
+        let inflated = inflate_block(&block, &mut buf).map(|_| buf);
+        if inflated_tx.send((idx, block_offset, inflated)).is_err() {
+            return; // reader is gone
+        }
+    }
+}
+
+impl<R: Read> Read for ParallelBgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.fill_buf()?.len());
+        buf[..n].copy_from_slice(&self.buf[self.begin..self.begin + n]);
+        self.begin += n;
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for ParallelBgzfReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.begin == self.buf.len() {
+            self.next_block()?;
+        }
+        Ok(&self.buf[self.begin..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.begin += amt;
+    }
+}
+
+impl<R: Read> Drop for ParallelBgzfReader<R> {
+    fn drop(&mut self) {
+        self.job_tx = None; // let the workers know there is no more block
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -1,31 +1,77 @@
-use gunzip::Decompressor;
+use gunzip::producer::Options;
+use gunzip::{Decompressor, Strategy};
+use std::io::Write;
 
 fn usage(program: &str) {
-    eprintln!("Usage: {} [-t]", program);
+    eprintln!("Usage: {} [-t] [-r] [-j threads] [-s strategy]", program);
     eprintln!("\tDecompresses .gz file read from stdin and outputs to stdout");
-    eprintln!("\t-t: employ two threads");
-    eprintln!("Example: {} < input.gz > output", program);
+    eprintln!("\t-t: employ two threads, same as -j 2");
+    eprintln!("\t-r: skip damaged data rather than failing, resuming at the next block or member");
+    eprintln!("\t    the damaged ranges are listed, and the exit status is 1 if there are any");
+    eprintln!("\t-j threads: employ the given # threads");
+    eprintln!("\t-s strategy: one of inline, pipelined, separate-checksum, speculative or parallel-members");
+    eprintln!("\t             defaults to inline for a single thread and pipelined for more");
+    eprintln!("Example: {} -j 8 < input.gz > output", program);
+}
+
+fn parse_strategy(arg: &str) -> Option<Strategy> {
+    match arg {
+        "inline" => Some(Strategy::Inline),
+        "pipelined" => Some(Strategy::Pipelined),
+        "separate-checksum" => Some(Strategy::SeparateChecksum),
+        "speculative" => Some(Strategy::Speculative),
+        "parallel-members" => Some(Strategy::ParallelMembers),
+        _ => None,
+    }
 }
 
 fn main() -> std::io::Result<()> {
     let mut args = std::env::args();
     let program = args.next().unwrap();
-    let multithread = match args.next() {
-        Some(arg) => {
-            if arg == "-t" {
-                true
-            } else {
+    let mut builder = Decompressor::builder();
+    while let Some(arg) = args.next() {
+        builder = match arg.as_str() {
+            "-t" => builder.threads(2),
+            "-r" => builder.options(Options {
+                recover: true,
+                ..Options::default()
+            }),
+            "-j" => match args.next().and_then(|x| x.parse().ok()) {
+                Some(threads) => builder.threads(threads),
+                None => {
+                    usage(&program);
+                    std::process::exit(-1);
+                }
+            },
+            "-s" => match args.next().as_deref().and_then(parse_strategy) {
+                Some(strategy) => builder.strategy(strategy),
+                None => {
+                    usage(&program);
+                    std::process::exit(-1);
+                }
+            },
+            _ => {
                 usage(&program);
                 std::process::exit(-1);
             }
-        }
-        None => false,
-    };
+        };
+    }
 
     let reader = std::io::stdin();
     let mut writer = std::io::stdout();
 
-    let mut decompressor = Decompressor::new(reader, multithread);
+    let mut decompressor = builder.build(reader);
     std::io::copy(&mut decompressor, &mut writer)?;
+    for (e, bits) in decompressor.damage() {
+        eprintln!(
+            "{}: damaged compressed bits {}..{}: {}",
+            program, bits.start, bits.end, e
+        );
+    }
+    if !decompressor.damage().is_empty() {
+        // the output is lossy
+        writer.flush()?;
+        std::process::exit(1);
+    }
     Ok(())
 }
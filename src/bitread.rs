@@ -1,9 +1,10 @@
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::mem::size_of;
 
 pub trait BitRead: Read {
     /// peak at least 24-bits without consuming
-    /// returns error if less than 24-bits are remaining
+    /// near the end of the input, the missing bits are zero-padded
+    /// returns error if no bit is remaining
     /// the caller is expected to apply the mask
     fn peek_bits(&mut self) -> std::io::Result<u32>;
 
@@ -14,7 +15,7 @@ pub trait BitRead: Read {
     fn byte_align(&mut self);
 
     /// read up to 24-bits and consume
-    /// return error if less than n-bits are remaining
+    /// return error if no bit is remaining
     #[inline(always)]
     fn read_bits(&mut self, n: u32) -> std::io::Result<u32> {
         debug_assert!(n <= 24);
@@ -26,6 +27,14 @@ pub trait BitRead: Read {
     /// indicate whether there is more data, even a single bit left
     /// it may refill the buffer
     fn has_data_left(&mut self) -> std::io::Result<bool>;
+
+    /// whether more bits have been consumed than available, i.e., zero-padded bits
+    fn is_overrun(&self) -> bool;
+
+    /// mark the start of the next unit to decode, e.g., a symbol,
+    /// for the readers that can roll back to it if the input turns out to be incomplete
+    #[inline(always)]
+    fn mark(&mut self) {}
 }
 
 const BUFFER_SIZE: usize = 16 << 10;
@@ -36,6 +45,7 @@ pub struct BitReader<R: Read> {
     buf: Vec<u8>,
     begin: usize,
     cap: usize,
+    offset: u64, // # bytes discarded from the front of the buffer so far
 }
 
 impl<R: Read> BitReader<R> {
@@ -46,16 +56,33 @@ impl<R: Read> BitReader<R> {
             buf: vec![0; BUFFER_SIZE],
             begin: 0,
             cap: 0,
+            offset: 0,
         }
     }
 
+    /// # bits consumed so far
+    pub fn bit_position(&self) -> u64 {
+        (self.offset + self.begin as u64) * 8 + self.nbits as u64
+    }
+
+    /// # bytes consumed so far, including the partially consumed byte
+    pub fn position(&self) -> u64 {
+        self.bit_position().div_ceil(8)
+    }
+
+    /// whether more bits have been consumed than available
+    /// this can happen when decoding the zero-padded bits at the end of the input
+    pub fn is_overrun(&self) -> bool {
+        self.begin > self.cap || (self.begin == self.cap && self.nbits > 0)
+    }
+
     fn buffer(&self) -> &[u8] {
-        &self.buf[self.begin..self.cap]
+        &self.buf[self.begin.min(self.cap)..self.cap]
     }
 
     /// # bits remaining within the buffer
     fn bit_len(&self) -> usize {
-        self.buffer().len() * 8 - self.nbits as usize
+        (self.buffer().len() * 8).saturating_sub(self.nbits as usize)
     }
 
     /// refill buffer
@@ -63,24 +90,98 @@ impl<R: Read> BitReader<R> {
     fn fill_buf(&mut self) -> std::io::Result<usize> {
         self.buf.copy_within(self.begin..self.cap, 0);
         self.cap -= self.begin;
+        self.offset += self.begin as u64;
         self.begin = 0;
         let n = self.read.read(&mut self.buf[self.cap..])?;
         self.cap += n;
         Ok(n)
     }
+
+    /// peek when less than 32-bits are in the buffer
+    /// the bits past the end of the input are zero-padded
+    #[cold]
+    fn peek_bits_slow(&mut self) -> std::io::Result<u32> {
+        if self.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        // fill_buf may not return enough bytes at once
+        while self.buffer().len() < size_of::<u32>() {
+            if self.fill_buf()? == 0 {
+                break;
+            }
+        }
+        if self.bit_len() == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        let mut bytes = [0u8; size_of::<u32>()];
+        let n = self.buffer().len().min(bytes.len());
+        bytes[..n].copy_from_slice(&self.buffer()[..n]);
+        Ok(u32::from_le_bytes(bytes) >> self.nbits)
+    }
+}
+
+impl<R: Read> BitReader<R> {
+    /// the underlying reader, along with the bytes read ahead from it but not yet consumed
+    /// the partially consumed byte, if any, counts as consumed
+    pub fn into_inner(mut self) -> (R, Vec<u8>) {
+        if self.is_overrun() {
+            return (self.read, Vec::new());
+        }
+        self.byte_align();
+        let unconsumed = self.buffer().to_vec();
+        (self.read, unconsumed)
+    }
+
+    /// the buffer from the partially consumed byte on, refilled up to n bytes
+    /// unless the input ends first
+    pub(crate) fn lookahead(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        if self.buf.len() < n {
+            self.buf.resize(n, 0);
+        }
+        while self.buffer().len() < n {
+            if self.fill_buf()? == 0 {
+                break;
+            }
+        }
+        Ok(self.buffer())
+    }
+
+    /// move back to the end of the input if more bits have been consumed than available
+    pub(crate) fn clear_overrun(&mut self) {
+        if self.is_overrun() {
+            self.begin = self.cap;
+            self.nbits = 0;
+        }
+    }
+}
+
+impl<R: Read + Seek> BitReader<R> {
+    /// seek the underlying reader to the byte offset, discarding the buffer
+    pub fn seek(&mut self, offset: u64) -> std::io::Result<()> {
+        self.read.seek(SeekFrom::Start(offset))?;
+        self.nbits = 0;
+        self.begin = 0;
+        self.cap = 0;
+        self.offset = offset;
+        Ok(())
+    }
 }
 
 impl<R: Read> Read for BitReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
         self.byte_align();
         // read off from the buffer first
-        let mut n = buf.len().min(self.buffer().len());
+        let n = buf.len().min(self.buffer().len());
         buf[..n].copy_from_slice(&self.buffer()[..n]);
         self.begin += n;
 
         // read the rest directly from Read
-        n += self.read.read(&mut buf[n..])?;
-        Ok(n)
+        let m = self.read.read(&mut buf[n..])?;
+        self.offset += m as u64;
+        Ok(n + m)
     }
 }
 
@@ -94,7 +195,6 @@ impl<R: Read> BitRead for BitReader<R> {
 
     #[inline(always)]
     fn consume(&mut self, n: u32) {
-        debug_assert!(self.bit_len() >= n as usize);
         self.nbits += n;
         self.begin += (self.nbits / 8) as usize;
         self.nbits %= 8;
@@ -102,11 +202,8 @@ impl<R: Read> BitRead for BitReader<R> {
 
     #[inline(always)]
     fn peek_bits(&mut self) -> std::io::Result<u32> {
-        // fill_buf may not return enough bytes at once
-        while self.buffer().len() < size_of::<u32>() {
-            if self.fill_buf()? == 0 {
-                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
-            }
+        if self.begin + size_of::<u32>() > self.cap {
+            return self.peek_bits_slow();
         }
         let bits = u32::from_le_bytes(
             self.buf[self.begin..self.begin + size_of::<u32>()]
@@ -117,20 +214,156 @@ impl<R: Read> BitRead for BitReader<R> {
     }
 
     fn has_data_left(&mut self) -> std::io::Result<bool> {
+        if self.is_overrun() {
+            return Ok(false);
+        }
         Ok(!self.buffer().is_empty() || self.fill_buf()? != 0)
     }
+
+    #[inline(always)]
+    fn is_overrun(&self) -> bool {
+        BitReader::is_overrun(self)
+    }
+}
+
+/// Reads bits from a slice held in memory, e.g., input buffered so far
+/// Reading past the end of the slice is reported by `is_overrun()`
+/// rather than by the errors of its methods alone
+pub struct SliceBitReader<'a> {
+    data: &'a [u8],
+    bit: usize,       // # bits consumed
+    is_cut_off: bool, // a read reached the end of data short of what it is after
+    mark: usize,      // bit position marked by mark()
+}
+
+impl<'a> SliceBitReader<'a> {
+    /// start reading at the given bit position within data
+    pub fn new(data: &'a [u8], bit: usize) -> Self {
+        Self {
+            data,
+            bit,
+            is_cut_off: false,
+            mark: bit,
+        }
+    }
+
+    /// # bits consumed from the beginning of data
+    pub fn bit_position(&self) -> usize {
+        self.bit
+    }
+
+    /// the bit position last marked, i.e., the start of the unit being decoded
+    pub fn marked_position(&self) -> usize {
+        self.mark
+    }
+
+    /// # bits remaining in data
+    pub fn bits_left(&self) -> usize {
+        (self.data.len() * 8).saturating_sub(self.bit)
+    }
+
+    /// whether more bits have been consumed than available, i.e., data is incomplete
+    pub fn is_overrun(&self) -> bool {
+        self.bit > self.data.len() * 8 || self.is_cut_off
+    }
+
+    fn buffer(&self) -> &'a [u8] {
+        &self.data[self.bit.div_ceil(8).min(self.data.len())..]
+    }
+}
+
+impl Read for SliceBitReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        self.byte_align();
+        let n = buf.len().min(self.buffer().len());
+        buf[..n].copy_from_slice(&self.buffer()[..n]);
+        self.bit += n * 8;
+        self.is_cut_off |= n < buf.len();
+        Ok(n)
+    }
+}
+
+impl BitRead for SliceBitReader<'_> {
+    #[inline(always)]
+    fn peek_bits(&mut self) -> std::io::Result<u32> {
+        let begin = self.bit / 8;
+        if let Some(bytes) = self.data.get(begin..begin + size_of::<u32>()) {
+            return Ok(u32::from_le_bytes(bytes.try_into().unwrap()) >> (self.bit % 8));
+        }
+        if begin >= self.data.len() {
+            self.is_cut_off = true;
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        let mut bytes = [0u8; size_of::<u32>()];
+        let n = (self.data.len() - begin).min(bytes.len());
+        bytes[..n].copy_from_slice(&self.data[begin..begin + n]);
+        Ok(u32::from_le_bytes(bytes) >> (self.bit % 8))
+    }
+
+    #[inline(always)]
+    fn consume(&mut self, n: u32) {
+        self.bit += n as usize;
+    }
+
+    fn byte_align(&mut self) {
+        self.bit = self.bit.div_ceil(8) * 8;
+    }
+
+    fn has_data_left(&mut self) -> std::io::Result<bool> {
+        Ok(self.bit < self.data.len() * 8)
+    }
+
+    #[inline(always)]
+    fn is_overrun(&self) -> bool {
+        SliceBitReader::is_overrun(self)
+    }
+
+    #[inline(always)]
+    fn mark(&mut self) {
+        self.mark = self.bit;
+    }
+}
+
+impl ReadUntil for SliceBitReader<'_> {
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>, limit: usize) -> std::io::Result<usize> {
+        if self.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+        self.byte_align();
+        let buffer = self.buffer();
+        let n = match buffer.iter().take(limit).position(|x| *x == byte) {
+            Some(pos) => pos + 1,
+            None if buffer.len() >= limit => limit,
+            None => {
+                self.is_cut_off = true;
+                buffer.len()
+            }
+        };
+        buf.extend_from_slice(&self.buffer()[..n]);
+        self.bit += n * 8;
+        Ok(n)
+    }
 }
 
 pub trait ReadUntil {
-    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> std::io::Result<usize>;
+    /// read up to and including byte, but no more than limit bytes, appending them to buf
+    /// returns # bytes read, which ends without byte if the limit or the end of input is reached
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>, limit: usize) -> std::io::Result<usize>;
 }
 
 impl<R: Read> ReadUntil for BitReader<R> {
-    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>, limit: usize) -> std::io::Result<usize> {
+        if self.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
         self.byte_align();
         let mut n = 0;
         loop {
-            match self.buffer().iter().position(|x| *x == byte) {
+            let m = self.buffer().len().min(limit - n);
+            match self.buffer()[..m].iter().position(|x| *x == byte) {
                 Some(pos) => {
                     buf.extend_from_slice(&self.buffer()[..pos + 1]);
                     n += pos + 1;
@@ -138,10 +371,10 @@ impl<R: Read> ReadUntil for BitReader<R> {
                     return Ok(n);
                 }
                 None => {
-                    buf.extend_from_slice(self.buffer());
-                    n += self.buffer().len();
-                    self.begin = self.cap;
-                    if self.fill_buf()? == 0 {
+                    buf.extend_from_slice(&self.buffer()[..m]);
+                    n += m;
+                    self.begin += m;
+                    if n == limit || self.fill_buf()? == 0 {
                         return Ok(n);
                     }
                 }
@@ -151,8 +384,8 @@ impl<R: Read> ReadUntil for BitReader<R> {
 }
 
 impl<R: ReadUntil> ReadUntil for &mut R {
-    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> std::io::Result<usize> {
-        (**self).read_until(byte, buf)
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>, limit: usize) -> std::io::Result<usize> {
+        (**self).read_until(byte, buf, limit)
     }
 }
 
@@ -174,4 +407,9 @@ impl<R: BitRead> BitRead for &mut R {
     fn has_data_left(&mut self) -> std::io::Result<bool> {
         (**self).has_data_left()
     }
+
+    #[inline(always)]
+    fn is_overrun(&self) -> bool {
+        (**self).is_overrun()
+    }
 }
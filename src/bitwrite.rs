@@ -0,0 +1,90 @@
+use std::io::Write;
+
+const BUFFER_SIZE: usize = 16 << 10;
+
+pub struct BitWriter<W: Write> {
+    write: W,
+    bits: u64,  // pending bits not yet written to the buffer, LSB first
+    nbits: u32, // # pending bits
+    buf: Vec<u8>,
+}
+
+impl<W: Write> BitWriter<W> {
+    pub fn new(write: W) -> Self {
+        Self {
+            write,
+            bits: 0,
+            nbits: 0,
+            buf: Vec::with_capacity(BUFFER_SIZE),
+        }
+    }
+
+    /// write the lowest n-bits, up to 32-bits
+    #[inline(always)]
+    pub fn write_bits(&mut self, bits: u32, n: u32) -> std::io::Result<()> {
+        debug_assert!(n <= 32);
+        debug_assert!(n == 32 || bits >> n == 0);
+        self.bits |= (bits as u64) << self.nbits;
+        self.nbits += n;
+        while self.nbits >= 8 {
+            self.buf.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+        if self.buf.len() >= BUFFER_SIZE {
+            self.flush_buf()?;
+        }
+        Ok(())
+    }
+
+    /// pad 0 to 7 zero bits to byte-align
+    pub fn byte_align(&mut self) -> std::io::Result<()> {
+        if self.nbits > 0 {
+            self.write_bits(0, 8 - self.nbits)?;
+        }
+        Ok(())
+    }
+
+    /// write bytes as they are
+    /// the writer must be byte-aligned
+    pub fn write_bytes(&mut self, xs: &[u8]) -> std::io::Result<()> {
+        debug_assert!(self.nbits == 0);
+        self.buf.extend_from_slice(xs);
+        if self.buf.len() >= BUFFER_SIZE {
+            self.flush_buf()?;
+        }
+        Ok(())
+    }
+
+    /// write out the buffered bytes
+    /// pending bits that do not form a whole byte are kept
+    pub fn flush_buf(&mut self) -> std::io::Result<()> {
+        self.write.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.write
+    }
+
+    /// byte-align, write out everything and return the underlying writer
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        self.byte_align()?;
+        self.flush_buf()?;
+        Ok(self.write)
+    }
+}
+
+impl<W: Write> Write for BitWriter<W> {
+    /// the writer must be byte-aligned
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_bytes(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_buf()?;
+        self.write.flush()
+    }
+}
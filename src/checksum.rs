@@ -10,6 +10,10 @@ pub trait Checksum {
     // total number of bytes updated so far
     fn len(&self) -> usize;
 
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     // reset the length counter
     fn reset_len(&mut self);
 }
@@ -28,6 +32,12 @@ impl Crc32Checksum {
     }
 }
 
+impl Default for Crc32Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Checksum for Crc32Checksum {
     fn update(&mut self, xs: &[u8]) {
         self.hasher.update(xs);
@@ -47,3 +57,53 @@ impl Checksum for Crc32Checksum {
         self.n = 0;
     }
 }
+
+const ADLER_MODULO: u32 = 65521;
+// largest n such that 255n(n+1)/2 + (n+1)(ADLER_MODULO-1) fits in u32
+const ADLER_NMAX: usize = 5552;
+
+pub struct Adler32Checksum {
+    a: u32,
+    b: u32,
+    n: usize,
+}
+
+impl Adler32Checksum {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0, n: 0 }
+    }
+}
+
+impl Default for Adler32Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Adler32Checksum {
+    fn update(&mut self, xs: &[u8]) {
+        for chunk in xs.chunks(ADLER_NMAX) {
+            for x in chunk {
+                self.a += *x as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULO;
+            self.b %= ADLER_MODULO;
+        }
+        self.n += xs.len();
+    }
+
+    fn checksum(&mut self) -> u32 {
+        let checksum = self.b << 16 | self.a;
+        (self.a, self.b) = (1, 0);
+        checksum
+    }
+
+    fn len(&self) -> usize {
+        self.n
+    }
+
+    fn reset_len(&mut self) {
+        self.n = 0;
+    }
+}
@@ -3,6 +3,11 @@ use crate::error::{Error, Result};
 pub const MAX_CODELENGTH: u32 = 15;
 pub const MAX_LL_SYMBOL: u32 = 288;
 
+/// order in which the code length code lengths are stored in a dynamic block header
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
 pub struct CodeBook {
     tree: Vec<(u32, u32)>, // bitcode, length
     max_length: u32,
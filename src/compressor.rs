@@ -0,0 +1,451 @@
+use crate::bitwrite::BitWriter;
+use crate::codebook::{CodeBook, CODE_LENGTH_ORDER, MAX_CODELENGTH};
+use crate::huffman_encoder::{code_lengths, HuffmanEncoder};
+use crate::lz77::{
+    Code, END_OF_BLOCK, MAX_DISTANCE, MAX_LENGTH, SYMBOL2BITS_DISTANCE, SYMBOL2BITS_LENGTH,
+};
+use std::io::Write;
+
+pub const DEFAULT_LEVEL: u32 = 6;
+pub const MAX_LEVEL: u32 = 9;
+
+const MIN_LENGTH: usize = 3;
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const WINDOW_MASK: usize = MAX_DISTANCE as usize - 1;
+const BLOCK_SIZE: usize = 64 << 10; // # input bytes per block
+const MAX_STORED_LEN: usize = 0xFFFF;
+const NUM_LL_SYMBOLS: usize = 286;
+const NUM_DIST_SYMBOLS: usize = 30;
+const MAX_CL_CODELENGTH: u32 = 7;
+
+pub struct Compressor {
+    max_chain: usize, // 0 means no compression, i.e., stored blocks only
+    data: Vec<u8>,    // up to 32kB of history followed by the pending input
+    pending: usize,   // index to data where the pending input begins
+    base: usize,      // # bytes discarded from the front of data so far
+    head: Vec<usize>, // head[hash] = 1 + most recent position with the hash, 0 if none
+    prev: Vec<usize>, // prev[pos % 32kB] = 1 + previous position with the same hash, 0 if none
+    codes: Vec<Code>,
+    fixed_ll: HuffmanEncoder,
+    fixed_dist: HuffmanEncoder,
+}
+
+impl Compressor {
+    /// level ranges from 0 (no compression) to 9 (best compression)
+    pub fn new(level: u32) -> Self {
+        let max_chain = match level.min(MAX_LEVEL) {
+            0 => 0,
+            1 => 4,
+            2 => 8,
+            3 => 16,
+            4 => 32,
+            5 => 64,
+            6 => 128,
+            7 => 256,
+            8 => 1024,
+            _ => 4096,
+        };
+        Self {
+            max_chain,
+            data: Vec::with_capacity(MAX_DISTANCE as usize + BLOCK_SIZE),
+            pending: 0,
+            base: 0,
+            head: vec![0; HASH_SIZE],
+            prev: vec![0; MAX_DISTANCE as usize],
+            codes: Vec::new(),
+            fixed_ll: HuffmanEncoder::new(CodeBook::default_ll()),
+            fixed_dist: HuffmanEncoder::new(CodeBook::default_dist()),
+        }
+    }
+
+    /// buffer the input and write out a block whenever enough input is gathered
+    pub fn write<W: Write>(
+        &mut self,
+        mut xs: &[u8],
+        writer: &mut BitWriter<W>,
+    ) -> std::io::Result<()> {
+        while !xs.is_empty() {
+            let n = xs.len().min(BLOCK_SIZE - (self.data.len() - self.pending));
+            self.data.extend_from_slice(&xs[..n]);
+            xs = &xs[n..];
+            if self.data.len() - self.pending == BLOCK_SIZE {
+                self.write_block(false, writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// write out the pending input followed by an empty stored block
+    /// so that everything written so far can be decompressed
+    pub fn flush<W: Write>(&mut self, writer: &mut BitWriter<W>) -> std::io::Result<()> {
+        if self.data.len() > self.pending {
+            self.write_block(false, writer)?;
+        }
+        self.write_stored(self.data.len(), false, writer)
+    }
+
+    /// write out the pending input as the final block
+    pub fn finish<W: Write>(&mut self, writer: &mut BitWriter<W>) -> std::io::Result<()> {
+        self.write_block(true, writer)
+    }
+
+    fn write_block<W: Write>(
+        &mut self,
+        is_final: bool,
+        writer: &mut BitWriter<W>,
+    ) -> std::io::Result<()> {
+        if self.max_chain == 0 {
+            self.write_stored(self.pending, is_final, writer)?;
+            self.slide();
+            return Ok(());
+        }
+
+        self.find_matches();
+        let mut ll_freqs = [0u32; NUM_LL_SYMBOLS];
+        let mut dist_freqs = [0u32; NUM_DIST_SYMBOLS];
+        for code in &self.codes {
+            match code {
+                Code::Literal(x) => ll_freqs[*x as usize] += 1,
+                Code::Dictionary { distance, length } => {
+                    ll_freqs[END_OF_BLOCK as usize + length_index(*length)] += 1;
+                    dist_freqs[distance_index(*distance)] += 1;
+                }
+                Code::EndOfBlock => unreachable!(),
+            }
+        }
+        ll_freqs[END_OF_BLOCK as usize] += 1;
+        // make sure there are at least two codes, which some decoders require
+        for freqs in [&mut ll_freqs[..], &mut dist_freqs[..]] {
+            let mut symbol = 0;
+            while freqs.iter().filter(|f| **f > 0).count() < 2 {
+                freqs[symbol] = freqs[symbol].max(1);
+                symbol += 1;
+            }
+        }
+
+        let ll_lengths = code_lengths(&ll_freqs, MAX_CODELENGTH);
+        let dist_lengths = code_lengths(&dist_freqs, MAX_CODELENGTH);
+        let header = DynamicHeader::new(&ll_lengths, &dist_lengths);
+        let dynamic_ll = HuffmanEncoder::new(CodeBook::new(&ll_lengths)?);
+        let dynamic_dist = HuffmanEncoder::new(CodeBook::new(&dist_lengths)?);
+
+        let stored_cost = stored_cost(self.data.len() - self.pending);
+        let fixed_cost = 3 + data_cost(&ll_freqs, &dist_freqs, &self.fixed_ll, &self.fixed_dist);
+        let dynamic_cost =
+            3 + header.cost + data_cost(&ll_freqs, &dist_freqs, &dynamic_ll, &dynamic_dist);
+
+        if stored_cost <= fixed_cost.min(dynamic_cost) {
+            self.write_stored(self.pending, is_final, writer)?;
+        } else if fixed_cost <= dynamic_cost {
+            writer.write_bits(is_final as u32 | 0b010, 3)?;
+            write_codes(&self.codes, &self.fixed_ll, &self.fixed_dist, writer)?;
+        } else {
+            writer.write_bits(is_final as u32 | 0b100, 3)?;
+            header.write(writer)?;
+            write_codes(&self.codes, &dynamic_ll, &dynamic_dist, writer)?;
+        }
+        self.slide();
+        Ok(())
+    }
+
+    /// write data[begin..] as one or more stored blocks
+    fn write_stored<W: Write>(
+        &self,
+        begin: usize,
+        is_final: bool,
+        writer: &mut BitWriter<W>,
+    ) -> std::io::Result<()> {
+        let mut chunks = self.data[begin..].chunks(MAX_STORED_LEN).peekable();
+        if chunks.peek().is_none() {
+            return write_stored_block(&[], is_final, writer);
+        }
+        while let Some(chunk) = chunks.next() {
+            write_stored_block(chunk, is_final && chunks.peek().is_none(), writer)?;
+        }
+        Ok(())
+    }
+
+    /// turn the pending input into codes
+    fn find_matches(&mut self) {
+        self.codes.clear();
+        let end = self.data.len();
+        let mut pos = self.pending;
+        while pos < end {
+            let (length, distance) = self.longest_match(pos);
+            if length >= MIN_LENGTH {
+                self.codes.push(Code::Dictionary {
+                    distance: distance as u16,
+                    length: length as u16,
+                });
+                for p in pos..pos + length {
+                    self.insert(p);
+                }
+                pos += length;
+            } else {
+                self.codes.push(Code::Literal(self.data[pos]));
+                self.insert(pos);
+                pos += 1;
+            }
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let xs = &self.data[pos..pos + MIN_LENGTH];
+        let h = (xs[0] as usize) << 10 ^ (xs[1] as usize) << 5 ^ xs[2] as usize;
+        h & (HASH_SIZE - 1)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if pos + MIN_LENGTH > self.data.len() {
+            return;
+        }
+        let h = self.hash(pos);
+        let abs = self.base + pos;
+        self.prev[abs & WINDOW_MASK] = self.head[h];
+        self.head[h] = abs + 1;
+    }
+
+    /// returns (length, distance) of the longest match found for data[pos..]
+    fn longest_match(&self, pos: usize) -> (usize, usize) {
+        let max_length = (self.data.len() - pos).min(MAX_LENGTH as usize);
+        if max_length < MIN_LENGTH {
+            return (0, 0);
+        }
+
+        let abs = self.base + pos;
+        let target = &self.data[pos..pos + max_length];
+        let mut best = (0, 0);
+        let mut next = self.head[self.hash(pos)];
+        let mut chain = self.max_chain;
+        while next > 0 && chain > 0 {
+            let candidate = next - 1;
+            let distance = abs - candidate;
+            if distance > MAX_DISTANCE as usize || candidate < self.base {
+                break;
+            }
+            let begin = candidate - self.base;
+            let xs = &self.data[begin..begin + max_length];
+            if xs[best.0.min(max_length - 1)] == target[best.0.min(max_length - 1)] {
+                let length = xs.iter().zip(target).take_while(|(x, y)| x == y).count();
+                if length > best.0 {
+                    best = (length, distance);
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+            let prev = self.prev[candidate & WINDOW_MASK];
+            if prev >= next {
+                break; // the entry has been overwritten by a more recent position
+            }
+            next = prev;
+            chain -= 1;
+        }
+        best
+    }
+
+    /// mark the pending input as written and discard history beyond 32kB
+    fn slide(&mut self) {
+        if self.data.len() > MAX_DISTANCE as usize {
+            let delta = self.data.len() - MAX_DISTANCE as usize;
+            self.data.drain(..delta);
+            self.base += delta;
+        }
+        self.pending = self.data.len();
+    }
+}
+
+/// header of a dynamic block, i.e., the code lengths of the LL and distance codes
+struct DynamicHeader {
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    cl_codes: Vec<(u32, u32)>, // run-length encoded code lengths: symbol, extra bits
+    cl_lengths: [u32; 19],
+    cost: usize, // # bits
+}
+
+impl DynamicHeader {
+    fn new(ll_lengths: &[u32], dist_lengths: &[u32]) -> Self {
+        let hlit = (ll_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1).max(257);
+        let hdist = dist_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1;
+        let lengths = [&ll_lengths[..hlit], &dist_lengths[..hdist]].concat();
+
+        // The code lengths contain LL codes and Distance codes as a single table
+        let mut cl_codes = Vec::new();
+        let mut idx = 0;
+        while idx < lengths.len() {
+            let len = lengths[idx];
+            let mut run = lengths[idx..].iter().take_while(|l| **l == len).count();
+            idx += run;
+            if len == 0 {
+                while run >= 11 {
+                    let n = run.min(138);
+                    cl_codes.push((18, n as u32 - 11));
+                    run -= n;
+                }
+                if run >= 3 {
+                    cl_codes.push((17, run as u32 - 3));
+                    run = 0;
+                }
+            } else {
+                cl_codes.push((len, 0));
+                run -= 1;
+                while run >= 3 {
+                    let n = run.min(6);
+                    cl_codes.push((16, n as u32 - 3));
+                    run -= n;
+                }
+            }
+            cl_codes.extend(std::iter::repeat_n((len, 0), run));
+        }
+
+        let mut cl_freqs = [0; 19];
+        for (symbol, _) in &cl_codes {
+            cl_freqs[*symbol as usize] += 1;
+        }
+        let cl_lengths: [u32; 19] = code_lengths(&cl_freqs, MAX_CL_CODELENGTH)
+            .try_into()
+            .unwrap();
+        let hclen = (CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|idx| cl_lengths[*idx] > 0)
+            .unwrap_or(0)
+            + 1)
+        .max(4);
+
+        let cost = 5
+            + 5
+            + 4
+            + 3 * hclen
+            + cl_codes
+                .iter()
+                .map(|(symbol, _)| (cl_lengths[*symbol as usize] + cl_extra_bits(*symbol)) as usize)
+                .sum::<usize>();
+
+        Self {
+            hlit,
+            hdist,
+            hclen,
+            cl_codes,
+            cl_lengths,
+            cost,
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut BitWriter<W>) -> std::io::Result<()> {
+        writer.write_bits(self.hlit as u32 - 257, 5)?;
+        writer.write_bits(self.hdist as u32 - 1, 5)?;
+        writer.write_bits(self.hclen as u32 - 4, 4)?;
+        for idx in CODE_LENGTH_ORDER.into_iter().take(self.hclen) {
+            writer.write_bits(self.cl_lengths[idx], 3)?;
+        }
+        let cl_encoder = HuffmanEncoder::new(CodeBook::new(&self.cl_lengths)?);
+        for (symbol, extra) in &self.cl_codes {
+            let (bitcode, len) = cl_encoder.encode(*symbol);
+            writer.write_bits(bitcode, len)?;
+            writer.write_bits(*extra, cl_extra_bits(*symbol))?;
+        }
+        Ok(())
+    }
+}
+
+fn cl_extra_bits(symbol: u32) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// index to SYMBOL2BITS_LENGTH for the length
+fn length_index(length: u16) -> usize {
+    SYMBOL2BITS_LENGTH.partition_point(|(_, base)| *base <= length as u32) - 1
+}
+
+/// index to SYMBOL2BITS_DISTANCE for the distance
+fn distance_index(distance: u16) -> usize {
+    SYMBOL2BITS_DISTANCE.partition_point(|(_, base)| *base <= distance as u32) - 1
+}
+
+/// # bits to store n bytes as stored blocks, assuming the worst-case padding
+fn stored_cost(n: usize) -> usize {
+    let nblocks = n.div_ceil(MAX_STORED_LEN).max(1);
+    nblocks * (3 + 7 + 32) + n * 8
+}
+
+/// # bits to encode the symbols, excluding the block header
+fn data_cost(
+    ll_freqs: &[u32],
+    dist_freqs: &[u32],
+    ll_encoder: &HuffmanEncoder,
+    dist_encoder: &HuffmanEncoder,
+) -> usize {
+    let ll_cost = ll_freqs
+        .iter()
+        .enumerate()
+        .map(|(symbol, freq)| {
+            let extra = if symbol > END_OF_BLOCK as usize {
+                SYMBOL2BITS_LENGTH[symbol - END_OF_BLOCK as usize].0
+            } else {
+                0
+            };
+            *freq as usize * (ll_encoder.encode(symbol as u32).1 + extra) as usize
+        })
+        .sum::<usize>();
+    let dist_cost = dist_freqs
+        .iter()
+        .enumerate()
+        .map(|(symbol, freq)| {
+            let extra = SYMBOL2BITS_DISTANCE[symbol].0;
+            *freq as usize * (dist_encoder.encode(symbol as u32).1 + extra) as usize
+        })
+        .sum::<usize>();
+    ll_cost + dist_cost
+}
+
+fn write_stored_block<W: Write>(
+    xs: &[u8],
+    is_final: bool,
+    writer: &mut BitWriter<W>,
+) -> std::io::Result<()> {
+    writer.write_bits(is_final as u32, 3)?;
+    writer.byte_align()?;
+    writer.write_bits(xs.len() as u32, 16)?;
+    writer.write_bits(!xs.len() as u32 & 0xFFFF, 16)?;
+    writer.write_bytes(xs)
+}
+
+fn write_codes<W: Write>(
+    codes: &[Code],
+    ll_encoder: &HuffmanEncoder,
+    dist_encoder: &HuffmanEncoder,
+    writer: &mut BitWriter<W>,
+) -> std::io::Result<()> {
+    for code in codes {
+        match code {
+            Code::Literal(x) => {
+                let (bitcode, len) = ll_encoder.encode(*x as u32);
+                writer.write_bits(bitcode, len)?;
+            }
+            Code::Dictionary { distance, length } => {
+                let idx = length_index(*length);
+                let (bitcode, len) = ll_encoder.encode(END_OF_BLOCK + idx as u32);
+                writer.write_bits(bitcode, len)?;
+                let (bits, base) = SYMBOL2BITS_LENGTH[idx];
+                writer.write_bits(*length as u32 - base, bits)?;
+
+                let idx = distance_index(*distance);
+                let (bitcode, len) = dist_encoder.encode(idx as u32);
+                writer.write_bits(bitcode, len)?;
+                let (bits, base) = SYMBOL2BITS_DISTANCE[idx];
+                writer.write_bits(*distance as u32 - base, bits)?;
+            }
+            Code::EndOfBlock => unreachable!(),
+        }
+    }
+    let (bitcode, len) = ll_encoder.encode(END_OF_BLOCK);
+    writer.write_bits(bitcode, len)
+}
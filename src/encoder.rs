@@ -0,0 +1,91 @@
+use crate::bitwrite::BitWriter;
+use crate::checksum::{Checksum, Crc32Checksum};
+use crate::compressor::Compressor;
+use crate::footer::Footer;
+use crate::header::Header;
+use std::io::Write;
+
+/// Compresses the data written to it into a single gzip member
+/// The member is completed by `finish()`, or when dropped
+pub struct GzEncoder<W: Write> {
+    writer: Option<BitWriter<W>>,
+    header: Option<Header>, // yet to be written
+    compressor: Compressor,
+    checksum: Crc32Checksum,
+}
+
+impl<W: Write> GzEncoder<W> {
+    /// level ranges from 0 (no compression) to 9 (best compression)
+    pub fn new(write: W, level: u32) -> Self {
+        Self::with_header(write, level, Header::new())
+    }
+
+    pub fn with_header(write: W, level: u32, header: Header) -> Self {
+        Self {
+            writer: Some(BitWriter::new(write)),
+            header: Some(header),
+            compressor: Compressor::new(level),
+            checksum: Crc32Checksum::new(),
+        }
+    }
+
+    /// write out the rest of the member and return the underlying writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.try_finish()?.into_inner()
+    }
+
+    fn try_finish(&mut self) -> std::io::Result<BitWriter<W>> {
+        let mut writer = self.writer.take().ok_or_else(finished)?;
+        if let Some(header) = self.header.take() {
+            header.write(&mut writer)?;
+        }
+        self.compressor.finish(&mut writer)?;
+        writer.byte_align()?;
+        let footer = Footer {
+            crc32: self.checksum.checksum(),
+            size: self.checksum.len() as u32,
+        };
+        footer.write(&mut writer)?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let writer = writer(&mut self.writer, &mut self.header)?;
+        self.compressor.write(buf, writer)?;
+        self.checksum.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let writer = writer(&mut self.writer, &mut self.header)?;
+        self.compressor.flush(writer)?;
+        writer.flush()
+    }
+}
+
+/// the writer, after writing out the header if not yet written
+fn writer<'a, W: Write>(
+    writer: &'a mut Option<BitWriter<W>>,
+    header: &mut Option<Header>,
+) -> std::io::Result<&'a mut BitWriter<W>> {
+    let writer = writer.as_mut().ok_or_else(finished)?;
+    if let Some(header) = header.take() {
+        header.write(&mut *writer)?;
+    }
+    Ok(writer)
+}
+
+fn finished() -> std::io::Error {
+    std::io::Error::other("gzip member has already been finished")
+}
+
+impl<W: Write> Drop for GzEncoder<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
@@ -1,42 +1,156 @@
+use crate::producer::BlockType;
 use std::fmt::Display;
 use std::io::ErrorKind;
 
 #[derive(Debug)]
 pub enum Error {
-    StdIoError(ErrorKind),
+    StdIoError(std::io::Error),
     EmptyInput,
     InvalidGzHeader,
+    HeaderFieldTooLong,
+    HeaderChecksumMismatch,
+    InvalidExtraField,
+    InvalidBgzfBlock,
+    InvalidVirtualOffset,
+    InvalidIndex,
+    InvalidZlibHeader,
+    MissingDictionary,
+    DictionaryMismatch,
     InvalidBlockType,
     BlockType0LenMismatch,
     InvalidCodeLengths,
     HuffmanDecoderCodeNotFound,
+    InvalidSymbol,
     DistanceTooMuch,
     EndOfBlockNotFound,
     ReadDynamicCodebook,
     ChecksumMismatch,
     SizeMismatch,
+    OutputLimitExceeded,
+    MemberOutputLimitExceeded,
+    RatioLimitExceeded,
+    MemberLimitExceeded,
+    /// the input ends prematurely at the compressed byte offset,
+    /// with the output decodable before it delivered in full, per `Options::salvage`
+    Truncated {
+        compressed_offset: u64,
+        output_len: u64,
+    },
+    /// the error along with where it has occurred
+    Context(Box<Error>, Context),
+}
+
+/// Where an error has occurred
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Context {
+    /// 1-based index of the gzip member, or 0 before the first header
+    pub member: usize,
+    /// # compressed bits consumed, if known
+    pub bit_position: Option<u64>,
+    /// # bytes of the output produced
+    pub output_position: u64,
+    /// 0-based index of the deflate block within the member and its type, if within a block
+    pub block: Option<(u64, BlockType)>,
+}
+
+impl Error {
+    /// the error without the context
+    pub fn inner(&self) -> &Error {
+        match self {
+            Self::Context(e, _) => e.inner(),
+            e => e,
+        }
+    }
+
+    /// where the error has occurred, if known
+    pub fn context(&self) -> Option<&Context> {
+        match self {
+            Self::Context(_, context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// kind of the underlying `std::io::Error`, or `Other`
+    pub fn kind(&self) -> ErrorKind {
+        match self.inner() {
+            Self::StdIoError(e) => e.kind(),
+            Self::Truncated { .. } => ErrorKind::UnexpectedEof,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// attach the context, replacing the existing one if any
+    pub(crate) fn with_context(self, context: Context) -> Self {
+        match self {
+            Self::Context(e, _) => Self::Context(e, context),
+            e => Self::Context(Box::new(e), context),
+        }
+    }
+
+    /// attach the context of the enclosing stream to an error of a member inflated on its own,
+    /// given the member index and the positions at which the member begins
+    pub(crate) fn rebase(self, member: usize, bit_position: u64, output_position: u64) -> Self {
+        let context = self.context().cloned().unwrap_or_default();
+        let context = Context {
+            member,
+            bit_position: context.bit_position.map(|bit| bit_position + bit),
+            output_position: output_position + context.output_position,
+            block: context.block,
+        };
+        self.with_context(context)
+    }
 }
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Self::StdIoError(e.kind())
+        Self::StdIoError(e)
     }
 }
 
 impl From<Error> for std::io::Error {
     fn from(value: Error) -> Self {
         match value {
-            Error::StdIoError(e) => Self::from(e),
-            e => Self::new(std::io::ErrorKind::Other, e),
+            Error::StdIoError(e) => e,
+            e => Self::new(e.kind(), e),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::StdIoError(e) => Some(e),
+            Self::Context(e, _) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Self::StdIoError(e) => write!(f, "{}", e),
+            Self::Context(e, context) => write!(f, "{} at {}", e, context),
+            e => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "member {}", self.member)?;
+        if let Some((idx, block_type)) = self.block {
+            write!(f, ", block {} ({:?})", idx, block_type)?;
+        }
+        if let Some(bit_position) = self.bit_position {
+            write!(
+                f,
+                ", compressed bit {} (byte {})",
+                bit_position,
+                bit_position / 8
+            )?;
+        }
+        write!(f, ", output byte {}", self.output_position)
     }
 }
 
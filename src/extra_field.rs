@@ -0,0 +1,122 @@
+use crate::error::{Error, Result};
+
+pub const BGZF_ID: [u8; 2] = *b"BC";
+pub const DICTZIP_ID: [u8; 2] = *b"RA";
+pub const APPLE_ID: [u8; 2] = *b"Ap";
+
+/// SI1, SI2, LEN
+const SUBFIELD_HEADER_SIZE: usize = 4;
+
+/// subfield of the gzip extra field
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Subfield<'a> {
+    pub id: [u8; 2], // SI1, SI2
+    pub data: &'a [u8],
+}
+
+/// subfields with a well-known layout
+#[derive(PartialEq, Eq, Debug)]
+pub enum KnownSubfield<'a> {
+    /// total size of the BGZF block, i.e., the gzip member, in bytes
+    Bgzf {
+        block_size: u32,
+    },
+    /// dictzip random access table with the compressed size of each chunk
+    Dictzip {
+        version: u16,
+        chunk_len: u16,
+        chunk_sizes: Vec<u16>,
+    },
+    /// Apple file information, opaque
+    Apple(&'a [u8]),
+    Unknown(Subfield<'a>),
+}
+
+impl<'a> Subfield<'a> {
+    pub fn decode(&self) -> Result<KnownSubfield<'a>> {
+        let known = match self.id {
+            BGZF_ID => {
+                let bsize: [u8; 2] = self.data.try_into().or(Err(Error::InvalidExtraField))?;
+                KnownSubfield::Bgzf {
+                    block_size: u16::from_le_bytes(bsize) as u32 + 1,
+                }
+            }
+            DICTZIP_ID => {
+                if self.data.len() < 6 {
+                    return Err(Error::InvalidExtraField);
+                }
+                let (header, sizes) = self.data.split_at(6);
+                let version = u16::from_le_bytes([header[0], header[1]]);
+                let chunk_len = u16::from_le_bytes([header[2], header[3]]);
+                let chunk_count = u16::from_le_bytes([header[4], header[5]]);
+                if sizes.len() != chunk_count as usize * 2 {
+                    return Err(Error::InvalidExtraField);
+                }
+                let chunk_sizes = sizes
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .collect();
+                KnownSubfield::Dictzip {
+                    version,
+                    chunk_len,
+                    chunk_sizes,
+                }
+            }
+            APPLE_ID => KnownSubfield::Apple(self.data),
+            _ => KnownSubfield::Unknown(*self),
+        };
+        Ok(known)
+    }
+}
+
+/// iterates over the subfields of the extra field
+/// yields an error and stops if a subfield exceeds the extra field
+pub struct Subfields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Subfields<'a> {
+    pub fn new(extra_field: &'a [u8]) -> Self {
+        Self { data: extra_field }
+    }
+
+    /// find the first subfield with the id
+    pub fn find_id(self, id: [u8; 2]) -> Result<Option<Subfield<'a>>> {
+        for subfield in self {
+            let subfield = subfield?;
+            if subfield.id == id {
+                return Ok(Some(subfield));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for Subfields<'a> {
+    type Item = Result<Subfield<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        if self.data.len() < SUBFIELD_HEADER_SIZE {
+            self.data = &[];
+            return Some(Err(Error::InvalidExtraField));
+        }
+        let id = [self.data[0], self.data[1]];
+        let len = u16::from_le_bytes([self.data[2], self.data[3]]) as usize;
+        let rest = &self.data[SUBFIELD_HEADER_SIZE..];
+        if len > rest.len() {
+            self.data = &[];
+            return Some(Err(Error::InvalidExtraField));
+        }
+        let (data, rest) = rest.split_at(len);
+        self.data = rest;
+        Some(Ok(Subfield { id, data }))
+    }
+}
+
+/// check that the subfield lengths add up to the extra field length
+pub fn validate(extra_field: &[u8]) -> Result<()> {
+    Subfields::new(extra_field).try_for_each(|subfield| subfield.map(|_| ()))
+}
@@ -1,5 +1,5 @@
 use crate::error::Result;
-use std::io::Read;
+use std::io::{Read, Write};
 
 pub struct Footer {
     pub crc32: u32,
@@ -15,4 +15,10 @@ impl Footer {
         let size = u32::from_le_bytes(b.try_into().unwrap());
         Ok(Self { crc32, size })
     }
+
+    pub fn write(&self, mut write: impl Write) -> Result<()> {
+        write.write_all(&self.crc32.to_le_bytes())?;
+        write.write_all(&self.size.to_le_bytes())?;
+        Ok(())
+    }
 }
@@ -1,6 +1,9 @@
 use crate::bitread::ReadUntil;
 use crate::error::{Error, Result};
-use std::io::Read;
+use crate::extra_field::Subfields;
+use crc32fast::Hasher;
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime};
 
 const ID1: u8 = 0x1f;
 const ID2: u8 = 0x8b;
@@ -10,6 +13,77 @@ const FHCRC: u8 = 2;
 const FEXTRA: u8 = 4;
 const FNAME: u8 = 8;
 const FCOMMENT: u8 = 16;
+const OS_UNKNOWN: u8 = 255;
+
+/// operating system on which the compression took place
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Os {
+    Fat,
+    Amiga,
+    Vms,
+    Unix,
+    VmCms,
+    AtariTos,
+    Hpfs,
+    Macintosh,
+    ZSystem,
+    CpM,
+    Tops20,
+    Ntfs,
+    Qdos,
+    AcornRiscos,
+    Unknown,
+    Other(u8),
+}
+
+impl From<u8> for Os {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Fat,
+            1 => Self::Amiga,
+            2 => Self::Vms,
+            3 => Self::Unix,
+            4 => Self::VmCms,
+            5 => Self::AtariTos,
+            6 => Self::Hpfs,
+            7 => Self::Macintosh,
+            8 => Self::ZSystem,
+            9 => Self::CpM,
+            10 => Self::Tops20,
+            11 => Self::Ntfs,
+            12 => Self::Qdos,
+            13 => Self::AcornRiscos,
+            OS_UNKNOWN => Self::Unknown,
+            x => Self::Other(x),
+        }
+    }
+}
+
+/// default maximum # bytes of the name and the comment, excluding the terminating zero
+pub const DEFAULT_MAX_FIELD_LEN: usize = 64 << 10;
+
+#[derive(Clone)]
+pub struct HeaderOptions {
+    /// accept a header whose crc16 does not match, e.g., the ones written by buggy writers
+    pub tolerate_crc16_mismatch: bool,
+    /// maximum # bytes of the name, excluding the terminating zero
+    pub max_name_len: usize,
+    /// maximum # bytes of the comment, excluding the terminating zero
+    pub max_comment_len: usize,
+    /// maximum # bytes of the extra field, up to 65535 by the format
+    pub max_extra_len: usize,
+}
+
+impl Default for HeaderOptions {
+    fn default() -> Self {
+        Self {
+            tolerate_crc16_mismatch: false,
+            max_name_len: DEFAULT_MAX_FIELD_LEN,
+            max_comment_len: DEFAULT_MAX_FIELD_LEN,
+            max_extra_len: u16::MAX as usize,
+        }
+    }
+}
 
 pub struct Header {
     pub header: [u8; 10],
@@ -21,12 +95,34 @@ pub struct Header {
 }
 
 impl Header {
-    pub fn read(mut reader: impl Read + ReadUntil) -> Result<Self> {
+    /// header without any optional field, zero modification time and unknown OS
+    pub fn new() -> Self {
+        let header = [ID1, ID2, DEFLATE, 0, 0, 0, 0, 0, 0, OS_UNKNOWN];
+        Self {
+            header,
+            extra_field: None,
+            name: None,
+            comment: None,
+            crc16: None,
+            size: header.len(),
+        }
+    }
+
+    pub fn read(reader: impl Read + ReadUntil) -> Result<Self> {
+        Self::read_with_options(reader, &HeaderOptions::default())
+    }
+
+    pub fn read_with_options(
+        mut reader: impl Read + ReadUntil,
+        options: &HeaderOptions,
+    ) -> Result<Self> {
         let mut buf = [0; 10];
         reader.read_exact(&mut buf)?;
         if buf[0..3] != [ID1, ID2, DEFLATE] || buf[3] & 0b11100000 != 0 {
             return Err(Error::InvalidGzHeader);
         }
+        let mut hasher = Hasher::new(); // over all header bytes for FHCRC
+        hasher.update(&buf);
 
         let mut header = Self {
             header: buf,
@@ -40,34 +136,145 @@ impl Header {
         if header.get_flg() & FEXTRA != 0 {
             let mut buf = [0u8; 2];
             reader.read_exact(&mut buf)?;
+            hasher.update(&buf);
             header.size += buf.len();
-            let n = u16::from_le_bytes(buf);
-            let mut buf = vec![0u8; n as usize];
+            let n = u16::from_le_bytes(buf) as usize;
+            if n > options.max_extra_len {
+                return Err(Error::HeaderFieldTooLong);
+            }
+            let mut buf = vec![0u8; n];
             reader.read_exact(&mut buf)?;
+            hasher.update(&buf);
             header.size += buf.len();
             header.extra_field = Some(buf);
         }
         if header.get_flg() & FNAME != 0 {
-            let mut buf = Vec::new();
-            header.size += reader.read_until(0, &mut buf)?;
+            let buf = read_field(&mut reader, options.max_name_len)?;
+            hasher.update(&buf);
+            header.size += buf.len();
             header.name = Some(buf);
         }
         if header.get_flg() & FCOMMENT != 0 {
-            let mut buf = Vec::new();
-            header.size += reader.read_until(0, &mut buf)?;
+            let buf = read_field(&mut reader, options.max_comment_len)?;
+            hasher.update(&buf);
+            header.size += buf.len();
             header.comment = Some(buf);
         }
         if header.get_flg() & FHCRC != 0 {
             let mut buf = [0u8, 2];
             reader.read_exact(&mut buf)?;
             header.size += buf.len();
-            header.crc16 = Some(u16::from_le_bytes(buf));
+            let crc16 = u16::from_le_bytes(buf);
+            if crc16 != hasher.finalize() as u16 && !options.tolerate_crc16_mismatch {
+                return Err(Error::HeaderChecksumMismatch);
+            }
+            header.crc16 = Some(crc16);
         }
 
         Ok(header)
     }
 
+    /// write the header, setting the flags according to the optional fields present
+    /// name and comment are zero-terminated if not already
+    /// crc16 is computed over the header bytes if present
+    pub fn write(&self, mut write: impl Write) -> Result<()> {
+        let mut buf = Vec::with_capacity(self.size);
+        buf.extend_from_slice(&self.header);
+        let mut flg = self.get_flg() & FTEXT;
+        if let Some(extra_field) = &self.extra_field {
+            flg |= FEXTRA;
+            let n = u16::try_from(extra_field.len()).or(Err(Error::InvalidGzHeader))?;
+            buf.extend_from_slice(&n.to_le_bytes());
+            buf.extend_from_slice(extra_field);
+        }
+        for (flag, field) in [(FNAME, &self.name), (FCOMMENT, &self.comment)] {
+            if let Some(field) = field {
+                flg |= flag;
+                buf.extend_from_slice(field);
+                if field.last() != Some(&0) {
+                    buf.push(0);
+                }
+            }
+        }
+        if self.crc16.is_some() {
+            flg |= FHCRC;
+            buf[3] = flg;
+            let crc16 = crc32fast::hash(&buf) as u16;
+            buf.extend_from_slice(&crc16.to_le_bytes());
+        }
+        buf[3] = flg;
+        write.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// modification time, None if not available
+    pub fn mtime(&self) -> Option<SystemTime> {
+        let mtime = u32::from_le_bytes(self.header[4..8].try_into().unwrap());
+        if mtime == 0 {
+            None
+        } else {
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime as u64))
+        }
+    }
+
+    /// extra flags, e.g., 2 for maximum compression and 4 for fastest
+    pub fn xfl(&self) -> u8 {
+        self.header[8]
+    }
+
+    pub fn os(&self) -> Os {
+        Os::from(self.header[9])
+    }
+
+    /// whether the content is probably ASCII text
+    pub fn is_text(&self) -> bool {
+        self.get_flg() & FTEXT != 0
+    }
+
+    /// original file name without the terminating zero
+    /// use `latin1_to_string` to decode
+    pub fn name(&self) -> Option<&[u8]> {
+        self.name.as_deref().map(strip_zero)
+    }
+
+    /// comment without the terminating zero
+    /// use `latin1_to_string` to decode
+    pub fn comment(&self) -> Option<&[u8]> {
+        self.comment.as_deref().map(strip_zero)
+    }
+
+    /// subfields of the extra field, None if there is no extra field
+    pub fn subfields(&self) -> Option<Subfields<'_>> {
+        self.extra_field.as_deref().map(Subfields::new)
+    }
+
     fn get_flg(&self) -> u8 {
         self.header[3]
     }
 }
+
+fn strip_zero(xs: &[u8]) -> &[u8] {
+    xs.strip_suffix(&[0]).unwrap_or(xs)
+}
+
+/// decode ISO 8859-1 bytes, as used by the gzip name and comment fields
+pub fn latin1_to_string(xs: &[u8]) -> String {
+    xs.iter().map(|x| *x as char).collect()
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// read a zero-terminated field of up to max_len bytes, excluding the terminator
+fn read_field(mut reader: impl ReadUntil, max_len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let n = reader.read_until(0, &mut buf, max_len.saturating_add(1))?;
+    match buf.last() {
+        Some(0) => Ok(buf),
+        _ if n > max_len => Err(Error::HeaderFieldTooLong),
+        _ => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+    }
+}
@@ -1,5 +1,5 @@
 // https://stackoverflow.com/questions/2602823/in-c-c-whats-the-simplest-way-to-reverse-the-order-of-bits-in-a-byte
-fn reverse_bits(mut bits: u32) -> u32 {
+pub(crate) fn reverse_bits(mut bits: u32) -> u32 {
     bits = (bits & 0xFF00) >> 8 | (bits & 0x00FF) << 8;
     bits = (bits & 0xF0F0) >> 4 | (bits & 0x0F0F) << 4;
     bits = (bits & 0xCCCC) >> 2 | (bits & 0x3333) << 2;
@@ -7,6 +7,7 @@ fn reverse_bits(mut bits: u32) -> u32 {
     bits
 }
 
+use crate::bitread::BitRead;
 use crate::codebook::CodeBook;
 use crate::error::{Error, Result};
 
@@ -109,4 +110,21 @@ impl HuffmanDecoder {
             Ok(self.lookup[base + idx as usize])
         }
     }
+
+    /// Decode the next symbol from the reader and consume its code
+    /// If no code matches, the bits looked up are consumed, such that the reader is overrun
+    /// if the mismatch may be due to the zero-padded bits past the end of the input
+    #[inline(always)]
+    pub fn read<B: BitRead>(&self, reader: &mut B) -> Result<u32> {
+        match self.decode(reader.peek_bits()?) {
+            Ok((symbol, len)) => {
+                reader.consume(len);
+                Ok(symbol)
+            }
+            Err(e) => {
+                reader.consume(self.primary_mask.count_ones());
+                Err(e)
+            }
+        }
+    }
 }
@@ -0,0 +1,129 @@
+use crate::codebook::CodeBook;
+use crate::huffman_decoder::reverse_bits;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+pub struct HuffmanEncoder {
+    /// codes[symbol] where code is the bit-order reversed huffman code, right-aligned
+    /// this is so that we can write out the code LSB first
+    codes: Vec<(u32, u32)>, // bitcode, length
+}
+
+impl HuffmanEncoder {
+    pub fn new(codebook: CodeBook) -> Self {
+        let codes = codebook
+            .into_iter()
+            .map(|(bitcode, len)| (reverse_bits(bitcode) >> (16 - len), len))
+            .collect();
+        Self { codes }
+    }
+
+    /// Returns the bitcode and its length for the symbol
+    #[inline(always)]
+    pub fn encode(&self, symbol: u32) -> (u32, u32) {
+        self.codes[symbol as usize]
+    }
+}
+
+/// Compute huffman code lengths from symbol frequencies
+/// such that no code is longer than max_length bits
+/// symbols with zero frequency get zero length
+pub fn code_lengths(freqs: &[u32], max_length: u32) -> Vec<u32> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let lengths = unlimited_code_lengths(&freqs);
+        if lengths.iter().all(|l| *l <= max_length) {
+            return lengths;
+        }
+        // flatten the distribution and try again
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = (*f >> 1).max(1);
+        }
+    }
+}
+
+fn unlimited_code_lengths(freqs: &[u32]) -> Vec<u32> {
+    let mut lengths = vec![0; freqs.len()];
+    let symbols = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| **f > 0)
+        .map(|(symbol, _)| symbol)
+        .collect::<Vec<_>>();
+    match symbols.len() {
+        0 => return lengths,
+        1 => {
+            lengths[symbols[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // parents[i] = parent of node i, leaves come first
+    let mut parents = vec![usize::MAX; symbols.len()];
+    let mut heap = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, symbol)| Reverse((freqs[*symbol] as u64, node)))
+        .collect::<BinaryHeap<_>>();
+    while heap.len() > 1 {
+        let Reverse((a, x)) = heap.pop().unwrap();
+        let Reverse((b, y)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[x] = node;
+        parents[y] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+
+    // parents always come after their children, so walk backwards from the root
+    let mut depths = vec![0; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    for (node, symbol) in symbols.into_iter().enumerate() {
+        lengths[symbol] = depths[node];
+    }
+    lengths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the lengths form a complete prefix code, i.e., the Kraft sum is exactly one
+    fn kraft_sum(lengths: &[u32]) -> f64 {
+        lengths
+            .iter()
+            .filter(|l| **l > 0)
+            .map(|l| 0.5f64.powi(*l as i32))
+            .sum()
+    }
+
+    #[test]
+    fn unlimited_lengths_follow_frequencies() {
+        let lengths = code_lengths(&[8, 4, 2, 1, 1, 0], 15);
+        assert_eq!(lengths, vec![1, 2, 3, 4, 4, 0]);
+    }
+
+    #[test]
+    fn single_symbol() {
+        assert_eq!(code_lengths(&[0, 0, 7, 0], 15), vec![0, 0, 1, 0]);
+        assert_eq!(code_lengths(&[0, 0], 15), vec![0, 0]);
+    }
+
+    #[test]
+    fn lengths_are_limited() {
+        // Fibonacci frequencies give a maximally skewed tree 29 levels deep
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        assert_eq!(unlimited_code_lengths(&freqs).iter().max(), Some(&29));
+        for max_length in [7, 15] {
+            let lengths = code_lengths(&freqs, max_length);
+            assert!(lengths.iter().all(|l| (1..=max_length).contains(l)));
+            assert_eq!(kraft_sum(&lengths), 1.0);
+        }
+    }
+}
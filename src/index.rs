@@ -0,0 +1,294 @@
+//! Random access into gzip files via checkpoints, similar to zlib's zran.c
+//!
+//! A checkpoint is taken at a deflate block boundary and records
+//! the compressed bit offset, the uncompressed offset and up to 32kB of the preceding history,
+//! from which inflating can be resumed.
+//!
+//! The index is persisted in the following binary format, with all integers in little-endian
+//!
+//! | field      | size        | description                                        |
+//! |------------|-------------|----------------------------------------------------|
+//! | magic      | 8           | `GZINDEX` followed by the format version 1         |
+//! | spacing    | 8           | minimum # uncompressed bytes between checkpoints   |
+//! | total_out  | 8           | # uncompressed bytes of the whole input            |
+//! | count      | 8           | # checkpoints                                      |
+//!
+//! followed by count checkpoints in increasing order of offsets, each of which is
+//!
+//! | field      | size        | description                                        |
+//! |------------|-------------|----------------------------------------------------|
+//! | bit_offset | 8           | compressed offset in bits                          |
+//! | out_offset | 8           | uncompressed offset in bytes                       |
+//! | window_len | 4           | # history bytes, at most 32768                     |
+//! | window     | window_len  | history preceding the checkpoint within the member |
+
+use crate::error::{Error, Result};
+use crate::lz77::MAX_DISTANCE;
+use crate::producer::{Format, Produce, Producer, Verifier};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const MAGIC: [u8; 8] = *b"GZINDEX\x01";
+pub const DEFAULT_SPACING: u64 = 1 << 20;
+
+pub struct Checkpoint {
+    pub bit_offset: u64,
+    pub out_offset: u64,
+    pub window: Vec<u8>,
+}
+
+pub struct Index {
+    pub spacing: u64,
+    pub total_out: u64,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+/// Records checkpoints while the caller iterates through a `Producer`
+pub struct IndexBuilder {
+    spacing: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl IndexBuilder {
+    /// spacing is the minimum # uncompressed bytes between checkpoints
+    pub fn new(spacing: u64) -> Self {
+        Self {
+            spacing,
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// to be called before each `next()` of the producer
+    /// records a checkpoint if the producer is at a block boundary far enough from the last one
+    pub fn observe<R: Read>(&mut self, producer: &Producer<R>) {
+        if !producer.is_at_block_boundary() {
+            return;
+        }
+        let out_offset = producer.total_out();
+        if let Some(last) = self.checkpoints.last() {
+            if out_offset < last.out_offset + self.spacing {
+                return;
+            }
+        }
+        self.checkpoints.push(Checkpoint {
+            bit_offset: producer.bit_position(),
+            out_offset,
+            window: producer.history().to_vec(),
+        });
+    }
+
+    pub fn finish(self, total_out: u64) -> Index {
+        Index {
+            spacing: self.spacing,
+            total_out,
+            checkpoints: self.checkpoints,
+        }
+    }
+}
+
+impl Index {
+    /// build the index by decompressing and verifying the whole input
+    pub fn build(read: impl Read, spacing: u64) -> Result<Self> {
+        let mut builder = IndexBuilder::new(spacing);
+        let mut producer = Producer::new(read);
+        let mut verifier = Verifier::new(Format::Gzip);
+        loop {
+            builder.observe(&producer);
+            let Some(produce) = producer.next() else {
+                break;
+            };
+            verifier.verify(&produce)?;
+            if let Produce::Err(e) = produce {
+                return Err(e);
+            }
+        }
+        Ok(builder.finish(producer.total_out()))
+    }
+
+    /// the last checkpoint at or before the uncompressed offset
+    pub fn checkpoint_before(&self, out_offset: u64) -> Option<&Checkpoint> {
+        let n = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.out_offset <= out_offset);
+        n.checked_sub(1).map(|idx| &self.checkpoints[idx])
+    }
+
+    pub fn write(&self, mut write: impl Write) -> Result<()> {
+        write.write_all(&MAGIC)?;
+        write.write_all(&self.spacing.to_le_bytes())?;
+        write.write_all(&self.total_out.to_le_bytes())?;
+        write.write_all(&(self.checkpoints.len() as u64).to_le_bytes())?;
+        for checkpoint in &self.checkpoints {
+            write.write_all(&checkpoint.bit_offset.to_le_bytes())?;
+            write.write_all(&checkpoint.out_offset.to_le_bytes())?;
+            write.write_all(&(checkpoint.window.len() as u32).to_le_bytes())?;
+            write.write_all(&checkpoint.window)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(mut read: impl Read) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        read.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::InvalidIndex);
+        }
+        let spacing = read_u64(&mut read)?;
+        let total_out = read_u64(&mut read)?;
+        let count = read_u64(&mut read)?;
+        let mut checkpoints = Vec::new();
+        for _ in 0..count {
+            let bit_offset = read_u64(&mut read)?;
+            let out_offset = read_u64(&mut read)?;
+            let mut buf = [0u8; 4];
+            read.read_exact(&mut buf)?;
+            let window_len = u32::from_le_bytes(buf) as usize;
+            if window_len > MAX_DISTANCE as usize {
+                return Err(Error::InvalidIndex);
+            }
+            let mut window = vec![0; window_len];
+            read.read_exact(&mut window)?;
+            if checkpoints
+                .last()
+                .is_some_and(|last: &Checkpoint| last.out_offset > out_offset)
+            {
+                return Err(Error::InvalidIndex);
+            }
+            checkpoints.push(Checkpoint {
+                bit_offset,
+                out_offset,
+                window,
+            });
+        }
+        Ok(Self {
+            spacing,
+            total_out,
+            checkpoints,
+        })
+    }
+}
+
+fn read_u64(mut read: impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    read.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads a gzip file from any uncompressed offset, resuming from the nearest checkpoint
+/// Members that begin after the checkpoint are verified against their footers
+pub struct IndexedReader<R: Read + Seek> {
+    producer: Producer<R>,
+    index: Index,
+    verifier: Verifier,
+    verifying: bool, // whether the current member is being verified
+    buf: Vec<u8>,
+    begin: usize,
+    pos: u64, // uncompressed offset of the next byte to be read
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// read should be positioned at the beginning of the gzip file
+    pub fn new(read: R, index: Index) -> Self {
+        Self {
+            producer: Producer::new(read),
+            index,
+            verifier: Verifier::new(Format::Gzip),
+            verifying: true,
+            buf: Vec::new(),
+            begin: 0,
+            pos: 0,
+        }
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// move to the uncompressed offset
+    pub fn seek_to(&mut self, out_offset: u64) -> Result<()> {
+        let from = self.index.checkpoint_before(out_offset);
+        let resume_offset = from.map_or(0, |checkpoint| checkpoint.out_offset);
+        if out_offset < self.pos || resume_offset > self.pos {
+            // resuming is closer than reading forward
+            match from {
+                Some(checkpoint) => self.producer.resume(
+                    checkpoint.bit_offset,
+                    &checkpoint.window,
+                    checkpoint.out_offset,
+                )?,
+                None => self.producer.rewind()?,
+            }
+            self.verifier = Verifier::new(Format::Gzip);
+            self.verifying = from.is_none();
+            self.buf.clear();
+            self.begin = 0;
+            self.pos = resume_offset;
+        }
+
+        // read forward
+        while self.pos < out_offset {
+            if self.begin == self.buf.len() && !self.next_data()? {
+                break;
+            }
+            let n = (self.buf.len() - self.begin).min((out_offset - self.pos) as usize);
+            self.begin += n;
+            self.pos += n as u64;
+        }
+        Ok(())
+    }
+
+    /// load the next chunk of data
+    /// returns false at the end of the input
+    fn next_data(&mut self) -> Result<bool> {
+        for produce in self.producer.by_ref() {
+            match &produce {
+                Produce::Header(_) => {
+                    self.verifying = true;
+                }
+                Produce::Err(_) => {}
+                _ if !self.verifying => {}
+                _ => self.verifier.verify(&produce)?,
+            }
+            match produce {
+                Produce::Data(xs) if !xs.is_empty() => {
+                    self.buf = xs;
+                    self.begin = 0;
+                    return Ok(true);
+                }
+                Produce::Err(e) => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.begin == self.buf.len() && !self.next_data()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.buf.len() - self.begin);
+        buf[..n].copy_from_slice(&self.buf[self.begin..self.begin + n]);
+        self.begin += n;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let out_offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.index.total_out.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.seek_to(out_offset)?;
+        Ok(self.pos)
+    }
+}
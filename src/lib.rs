@@ -1,99 +1,547 @@
+pub mod bgzf;
 pub mod bitread;
+pub mod bitwrite;
 pub mod checksum;
 pub mod codebook;
+pub mod compressor;
+pub mod encoder;
 pub mod error;
+pub mod extra_field;
 pub mod footer;
 pub mod header;
 pub mod huffman_decoder;
+pub mod huffman_encoder;
+pub mod index;
 pub mod lz77;
+pub mod members;
 pub mod producer;
+pub mod push;
 pub mod sliding_window;
+pub mod speculative;
+pub mod zlib;
 
-use crate::{checksum::Checksum, error::Error, producer::Producer};
+use crate::{header::Header, producer::Producer};
 
-use checksum::Crc32Checksum;
-use producer::Produce;
+use error::Context;
+use members::ParallelMemberProducer;
+use producer::{Format, Options, Produce, TrailingPolicy, Verifier};
+use speculative::SpeculativeProducer;
 
-use std::io::Read;
+use std::any::Any;
+use std::io::{BufRead, Read};
+use std::ops::Range;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::thread::JoinHandle;
+
+pub use encoder::GzEncoder;
+
+/// default # chunks the producer thread may run ahead of the consumer
+pub const DEFAULT_IN_FLIGHT: usize = 4;
+
+/// How the work is split among the threads
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Strategy {
+    /// inflate and verify on the calling thread
+    Inline,
+    /// inflate on a producer thread, while the calling thread verifies and delivers the data
+    Pipelined,
+    /// inflate on a producer thread, verify on a checksum thread,
+    /// and deliver the data on the calling thread
+    /// worth it only if the calling thread would otherwise be the bottleneck (see README)
+    SeparateChecksum,
+    /// inflate chunks of each gzip member speculatively on all threads (see `speculative`)
+    /// the whole compressed input is held in memory
+    /// only gzip is supported, and other formats fall back to `Pipelined`,
+    /// as do `Options::recover` and `Options::salvage`
+    Speculative,
+    /// inflate the members of multi-member gzip files concurrently on all threads
+    /// (see `members`)
+    /// the whole compressed input is held in memory
+    /// only gzip is supported, and other formats fall back to `Pipelined`,
+    /// as do `Options::single_member`, `TrailingPolicy::IgnoreAfterFirstMember`,
+    /// `Options::recover` and `Options::salvage`
+    ParallelMembers,
+}
+
+/// Configures the # threads and the strategy of a `Decompressor`
+#[derive(Clone)]
+pub struct DecompressorBuilder {
+    threads: usize,
+    strategy: Option<Strategy>, // chosen by the # threads if None
+    in_flight: usize,
+    options: Options,
+}
+
+impl DecompressorBuilder {
+    pub fn new() -> Self {
+        Self {
+            threads: 1,
+            strategy: None,
+            in_flight: DEFAULT_IN_FLIGHT,
+            options: Options::default(),
+        }
+    }
+
+    /// # threads, including the calling thread
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// defaults to `Inline` for a single thread and `Pipelined` for more,
+    /// so `Speculative` and `ParallelMembers`, which hold the whole input in memory, are opt-in
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    /// # chunks (each up to 64kB) the producer thread may run ahead of the consumer
+    /// before it blocks, so that memory usage stays flat
+    pub fn in_flight(mut self, in_flight: usize) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build<R: Read + Send + 'static>(self, read: R) -> Decompressor {
+        let strategy = self.strategy.unwrap_or(match self.threads {
+            1 => Strategy::Inline,
+            _ => Strategy::Pipelined,
+        });
+        match strategy {
+            Strategy::Inline => Decompressor::with_options(read, self.options),
+            Strategy::Speculative
+                if self.options.format == Format::Gzip
+                    && !self.options.recover
+                    && !self.options.salvage =>
+            {
+                // the producer verifies the checksums itself as it resolves the chunks in parallel
+                let verifier = Verifier::new(Format::Raw);
+                let producer = SpeculativeProducer::with_options(read, self.threads, self.options);
+                Decompressor::from_iter(Box::new(producer), None, verifier)
+            }
+            Strategy::ParallelMembers
+                if self.options.format == Format::Gzip
+                    && !self.options.single_member
+                    && self.options.trailing != TrailingPolicy::IgnoreAfterFirstMember
+                    && !self.options.recover
+                    && !self.options.salvage =>
+            {
+                // the workers verify the checksums of the members themselves
+                let verifier = Verifier::new(Format::Raw);
+                let producer =
+                    ParallelMemberProducer::with_options(read, self.threads, self.options);
+                Decompressor::from_iter(Box::new(producer), None, verifier)
+            }
+            Strategy::Pipelined | Strategy::Speculative | Strategy::ParallelMembers => {
+                Decompressor::pipelined(read, self.in_flight, self.options)
+            }
+            Strategy::SeparateChecksum => {
+                Decompressor::separate_checksum(read, self.in_flight, self.options)
+            }
+        }
+    }
+}
+
+impl Default for DecompressorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Source of the items to decompress, which may be able to inflate directly into the caller's buffer
+trait Source: Iterator<Item = Produce> {
+    /// see `Producer::read_data()`
+    fn read_data(&mut self, _buf: &mut [u8]) -> error::Result<usize> {
+        Ok(0)
+    }
+
+    /// see `Producer::into_inner()`; None if the reader is not at hand
+    fn into_inner(self: Box<Self>) -> Option<(Box<dyn Any>, Vec<u8>)> {
+        None
+    }
+
+    /// see `Producer::context()`; None if the producer is not at hand
+    fn context(&self) -> Option<Context> {
+        None
+    }
+}
+
+impl<R: Read + 'static> Source for Producer<R> {
+    fn read_data(&mut self, buf: &mut [u8]) -> error::Result<usize> {
+        Producer::read_data(self, buf)
+    }
+
+    fn into_inner(self: Box<Self>) -> Option<(Box<dyn Any>, Vec<u8>)> {
+        let (read, unconsumed) = Producer::into_inner(*self);
+        Some((Box::new(read), unconsumed))
+    }
+
+    fn context(&self) -> Option<Context> {
+        Some(Producer::context(self))
+    }
+}
+
+impl<R: Read> Source for SpeculativeProducer<R> {}
+impl<R: Read> Source for ParallelMemberProducer<R> {}
+impl Source for std::sync::mpsc::IntoIter<Produce> {}
+impl Source for std::iter::Empty<Produce> {}
 
 pub struct Decompressor {
-    iter: Box<dyn Iterator<Item = Produce>>,
+    iter: Box<dyn Source>,
+    recycle: Option<Sender<Vec<u8>>>, // returns consumed buffers to the producer thread
+    threads: Vec<JoinHandle<()>>,     // joined at the end of the stream or on drop
     buf: Vec<u8>,
     begin: usize,
-    checksum: Crc32Checksum,
+    verifier: Verifier,
+    total_in: Option<u64>,
+    trailing: Option<(TrailingPolicy, u64)>,
+    damage: Vec<(error::Error, Range<u64>)>,
+    header: Option<Header>,
+    error: Option<std::io::Error>, // deferred until the data read before it is returned
 }
 
 impl Decompressor {
-    pub fn new<R: Read + Send + 'static>(read: R, multithread: bool) -> Self {
-        let iter = if multithread {
-            let (tx, rx) = std::sync::mpsc::channel::<Produce>();
-            std::thread::spawn(move || {
-                for produce in Producer::new(read) {
-                    tx.send(produce)
-                        .expect("error while transmitting produce over the channel");
-                }
-            });
+    /// single-threaded; see `builder()` for multithreading
+    pub fn new<R: Read + 'static>(read: R) -> Self {
+        Self::with_options(read, Options::default())
+    }
 
-            Box::new(rx.into_iter()) as Box<dyn Iterator<Item = Produce>>
-        } else {
-            Box::new(Producer::new(read))
-        };
+    pub fn with_options<R: Read + 'static>(read: R, options: Options) -> Self {
+        let verifier = verifier(&options);
+        let producer = Producer::with_options(read, options);
+        Self::from_iter(Box::new(producer), None, verifier)
+    }
+
+    pub fn builder() -> DecompressorBuilder {
+        DecompressorBuilder::new()
+    }
+
+    /// the producer thread blocks once in_flight chunks are waiting to be consumed
+    fn pipelined<R: Read + Send + 'static>(read: R, in_flight: usize, options: Options) -> Self {
+        let verifier = verifier(&options);
+        let (tx, rx) = sync_channel::<Produce>(in_flight);
+        let (recycle_tx, recycle_rx) = channel::<Vec<u8>>();
+        let producer = spawn_producer(read, options, tx, recycle_rx);
+        let mut decompressor =
+            Self::from_iter(Box::new(rx.into_iter()), Some(recycle_tx), verifier);
+        decompressor.threads.push(producer);
+        decompressor
+    }
 
+    /// the checksum thread sits in between the producer thread and the calling thread,
+    /// each stage buffering up to in_flight chunks
+    fn separate_checksum<R: Read + Send + 'static>(
+        read: R,
+        in_flight: usize,
+        options: Options,
+    ) -> Self {
+        let mut verifier = verifier(&options);
+        let (tx, rx) = sync_channel::<Produce>(in_flight);
+        let (verified_tx, verified_rx) = sync_channel::<Produce>(in_flight);
+        let (recycle_tx, recycle_rx) = channel::<Vec<u8>>();
+        let producer = spawn_producer(read, options, tx, recycle_rx);
+        let checksum = std::thread::spawn(move || {
+            for produce in rx {
+                let produce = match verifier.verify(&produce) {
+                    Ok(()) => produce,
+                    Err(e) => Produce::Err(e),
+                };
+                let is_err = matches!(produce, Produce::Err(_));
+                if verified_tx.send(produce).is_err() || is_err {
+                    // the consumer has been dropped, or nothing is to follow the error
+                    return;
+                }
+            }
+        });
+        // the data has been verified already
+        let verifier = Verifier::new(Format::Raw);
+        let mut decompressor = Self::from_iter(
+            Box::new(verified_rx.into_iter()),
+            Some(recycle_tx),
+            verifier,
+        );
+        decompressor.threads = vec![producer, checksum];
+        decompressor
+    }
+
+    fn from_iter(
+        iter: Box<dyn Source>,
+        recycle: Option<Sender<Vec<u8>>>,
+        verifier: Verifier,
+    ) -> Self {
         Self {
             iter,
+            recycle,
+            threads: Vec::new(),
             buf: vec![],
             begin: 0,
-            checksum: Crc32Checksum::new(),
+            verifier,
+            total_in: None,
+            trailing: None,
+            damage: Vec::new(),
+            header: None,
+            error: None,
         }
     }
 
-    fn fill_buf(&mut self) -> std::io::Result<usize> {
+    /// header of the gzip member whose data is being read
+    /// None until the first header is reached
+    pub fn current_header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// # compressed bytes consumed, available once the end of the stream is reached
+    /// for raw deflate streams, the caller may continue parsing the input from this offset
+    pub fn total_in(&self) -> Option<u64> {
+        self.total_in
+    }
+
+    /// # trailing bytes ignored at the end of the input per `Options::trailing`,
+    /// and the policy applied to them (see `Produce::Trailing`)
+    /// None if nothing has been ignored so far
+    pub fn trailing(&self) -> Option<(TrailingPolicy, u64)> {
+        self.trailing
+    }
+
+    /// the damaged input reported so far per `Options::recover`,
+    /// with the error and the range of compressed bit positions of each (see `Produce::Damaged`)
+    pub fn damage(&self) -> &[(error::Error, Range<u64>)] {
+        &self.damage
+    }
+
+    /// the underlying reader, along with the compressed bytes read ahead from it but not consumed,
+    /// e.g., what follows the gzip stream in a container, once the end of the stream is reached
+    /// None if R is not the type of the reader or the reader is not owned by the calling thread,
+    /// i.e., only single-threaded decompressors support it
+    /// the data not yet read from the decompressor is discarded
+    pub fn into_inner<R: Read + 'static>(mut self) -> Option<(R, Vec<u8>)> {
+        let iter = std::mem::replace(&mut self.iter, Box::new(std::iter::empty()));
+        let (read, unconsumed) = iter.into_inner()?;
+        Some((*read.downcast::<R>().ok()?, unconsumed))
+    }
+
+    fn recycle(&self, buf: Vec<u8>) {
+        if let Some(recycle) = &self.recycle {
+            // the producer thread may have finished already
+            let _ = recycle.send(buf);
+        }
+    }
+
+    /// replace buf with the next chunk of data
+    /// returns its size, or 0 at the end of the stream
+    fn next_chunk(&mut self) -> std::io::Result<usize> {
         loop {
-            match self.iter.next() {
+            let produce = self.iter.next();
+            if let Some(produce) = &produce {
+                self.verifier
+                    .verify(produce)
+                    .map_err(|e| match self.iter.context() {
+                        // where the producer is at, i.e., right past the footer
+                        Some(context) => e.with_context(context),
+                        None => e,
+                    })?;
+            }
+            match produce {
                 Some(Produce::Err(e)) => {
                     return Err(e.into());
                 }
-                Some(Produce::Header(_)) => { /* nothing to do */ }
+                Some(Produce::Header(header)) => {
+                    self.header = Some(header);
+                }
+                Some(Produce::ZlibHeader(_))
+                | Some(Produce::Footer(_))
+                | Some(Produce::ZlibFooter(_)) => { /* nothing to do */ }
                 Some(Produce::Data(xs)) => {
                     if xs.is_empty() {
+                        self.recycle(xs);
                         continue;
                     }
-                    self.checksum.update(&xs);
-                    self.buf = xs;
+                    let used = std::mem::replace(&mut self.buf, xs);
+                    self.recycle(used);
                     self.begin = 0;
                     return Ok(self.buf.len());
                 }
-                Some(Produce::Footer(footer)) => {
-                    if self.checksum.checksum() != footer.crc32 {
-                        return Err(Error::ChecksumMismatch.into());
-                    }
-
-                    if self.checksum.len() & 0xFFFFFFFF != footer.size as usize {
-                        return Err(Error::SizeMismatch.into());
-                    }
+                Some(Produce::Trailing(policy, len)) => {
+                    self.trailing = Some((policy, len));
+                }
+                Some(Produce::Damaged(e, bits)) => {
+                    self.damage.push((e, bits));
+                }
+                Some(Produce::End(total_in)) => {
+                    self.total_in = Some(total_in);
+                }
+                None => {
+                    self.join()?;
+                    return Ok(0);
+                }
+            }
+        }
+    }
 
-                    self.checksum.reset_len();
+    /// join the threads, if any, reporting their panics as an error
+    fn join(&mut self) -> std::io::Result<()> {
+        let mut result = Ok(());
+        for thread in self.threads.drain(..) {
+            if let Err(payload) = thread.join() {
+                let msg = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown cause");
+                if result.is_ok() {
+                    result = Err(std::io::Error::other(format!("thread panicked: {}", msg)));
                 }
-                None => return Ok(0),
             }
         }
+        result
+    }
+}
+
+/// Decompress the gzip data held in memory at once
+/// The output is pre-sized according to ISIZE of the last member
+pub fn decompress_to_vec(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut producer = Producer::new(data);
+    let mut verifier = Verifier::new(Format::Gzip);
+    let mut out = vec![0; expected_size(data)];
+    let mut len = 0;
+    loop {
+        // inflate directly into out until it is full
+        let n = producer.read_data(&mut out[len..])?;
+        if n > 0 {
+            verifier.update(&out[len..len + n]);
+            len += n;
+            continue;
+        }
+        let Some(produce) = producer.next() else {
+            break;
+        };
+        verifier.verify(&produce)?;
+        match produce {
+            Produce::Err(e) => return Err(e.into()),
+            Produce::Data(xs) => {
+                // out of room
+                out.truncate(len);
+                out.extend_from_slice(&xs);
+                len = out.len();
+                out.resize(len * 2, 0);
+            }
+            _ => {}
+        }
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+/// ISIZE of the last member, bounded by the maximum compression ratio of deflate
+fn expected_size(data: &[u8]) -> usize {
+    let Some(isize) = data.len().checked_sub(4).map(|n| &data[n..]) else {
+        return 0;
+    };
+    let isize = u32::from_le_bytes(isize.try_into().unwrap()) as usize;
+    isize.min(data.len().saturating_mul(1032))
+}
+
+/// the verifier of the items produced with the options
+/// the producer verifies the checksums itself in recovery mode
+fn verifier(options: &Options) -> Verifier {
+    match options.recover {
+        true => Verifier::new(Format::Raw),
+        false => Verifier::new(options.format),
+    }
+}
+
+/// spawn the thread inflating into tx, reusing the buffers from recycle_rx
+fn spawn_producer<R: Read + Send + 'static>(
+    read: R,
+    options: Options,
+    tx: SyncSender<Produce>,
+    recycle_rx: Receiver<Vec<u8>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut producer = Producer::with_options(read, options);
+        while let Some(produce) = producer.next() {
+            if tx.send(produce).is_err() {
+                // the consumer has been dropped
+                return;
+            }
+            for buf in recycle_rx.try_iter() {
+                producer.recycle(buf);
+            }
+        }
+    })
+}
+
+impl Drop for Decompressor {
+    fn drop(&mut self) {
+        // dropping the receiver stops the threads at their next send
+        self.iter = Box::new(std::iter::empty());
+        let _ = self.join();
     }
 }
 
 impl Read for Decompressor {
-    fn read(&mut self, mut buf: &mut [u8]) -> std::io::Result<usize> {
+    /// the data read before an error is returned first, and the error by the next call
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
         let mut nbytes = 0;
+        match self.read_helper(buf, &mut nbytes) {
+            Err(e) if nbytes > 0 => {
+                self.error = Some(e);
+                Ok(nbytes)
+            }
+            result => result.map(|_| nbytes),
+        }
+    }
+}
+
+impl Decompressor {
+    /// copy into buf, counting the bytes copied in nbytes
+    fn read_helper(&mut self, mut buf: &mut [u8], nbytes: &mut usize) -> std::io::Result<()> {
         loop {
             let n = buf.len().min(self.buf[self.begin..].len());
             buf[..n].copy_from_slice(&self.buf[self.begin..self.begin + n]);
             buf = &mut buf[n..];
-            nbytes += n;
+            *nbytes += n;
             self.begin += n;
 
-            if buf.is_empty() || self.fill_buf()? == 0 {
+            if buf.is_empty() {
+                break;
+            }
+            // inflate directly into the caller's buffer if possible
+            let n = self.iter.read_data(buf)?;
+            if n > 0 {
+                self.verifier.update(&buf[..n]);
+                buf = &mut buf[n..];
+                *nbytes += n;
+            } else if self.next_chunk()? == 0 {
                 break;
             }
         }
-        Ok(nbytes)
+        Ok(())
+    }
+}
+
+impl BufRead for Decompressor {
+    /// the chunks produced are handed out as they are, without copying
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.begin == self.buf.len() {
+            self.next_chunk()?;
+        }
+        Ok(&self.buf[self.begin..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.begin = (self.begin + amt).min(self.buf.len());
     }
 }
@@ -17,7 +17,7 @@ pub enum Code {
 pub enum DecodeResult {
     Done(usize),         // all symbols are exhausted and written
     WindowIsFull(usize), // cannot proceed further because the window is full
-    Error(Error),
+    Error(Error, usize), // cannot proceed further, with # bytes written before the error
 }
 
 pub fn decode<B: BitRead>(
@@ -27,16 +27,63 @@ pub fn decode<B: BitRead>(
     ll_decoder: &HuffmanDecoder,
     dist_decoder: &HuffmanDecoder,
 ) -> Result<DecodeResult> {
-    let mut idx = boundary; // position to write to
-    if idx + MAX_LENGTH as usize >= window.len() {
-        return Ok(DecodeResult::WindowIsFull(idx - boundary));
+    let (history, out) = window.split_at_mut(boundary);
+    decode_into(out, history, reader, ll_decoder, dist_decoder)
+}
+
+/// Same as `decode()`, but writes to out, which is preceded by the history
+pub fn decode_into<B: BitRead>(
+    out: &mut [u8],
+    history: &[u8],
+    reader: &mut B,
+    ll_decoder: &HuffmanDecoder,
+    dist_decoder: &HuffmanDecoder,
+) -> Result<DecodeResult> {
+    decode_symbols::<B, false>(out, history, reader, ll_decoder, dist_decoder)
+}
+
+/// Same as `decode_into()`, but stops short of the symbol decoded from the zero-padded bits
+/// past the end of the input, such that the bytes written are exactly those decodable
+/// The start of each symbol is marked on the reader, so that it can resume from the symbol
+/// that has failed once more input is available
+pub fn decode_into_exact<B: BitRead>(
+    out: &mut [u8],
+    history: &[u8],
+    reader: &mut B,
+    ll_decoder: &HuffmanDecoder,
+    dist_decoder: &HuffmanDecoder,
+) -> Result<DecodeResult> {
+    decode_symbols::<B, true>(out, history, reader, ll_decoder, dist_decoder)
+}
+
+fn decode_symbols<B: BitRead, const EXACT: bool>(
+    out: &mut [u8],
+    history: &[u8],
+    reader: &mut B,
+    ll_decoder: &HuffmanDecoder,
+    dist_decoder: &HuffmanDecoder,
+) -> Result<DecodeResult> {
+    let mut idx = 0; // position to write to
+    if idx + MAX_LENGTH as usize >= out.len() {
+        return Ok(DecodeResult::WindowIsFull(idx));
     }
 
     loop {
-        let code = read_next_code(reader, ll_decoder, dist_decoder)?;
+        if EXACT {
+            reader.mark();
+        }
+        let code = match read_next_code(reader, ll_decoder, dist_decoder) {
+            Ok(_) if EXACT && reader.is_overrun() => {
+                // decoded from the zero-padded bits past the end of the input
+                let e = std::io::Error::from(std::io::ErrorKind::UnexpectedEof);
+                return Ok(DecodeResult::Error(e.into(), idx));
+            }
+            Ok(code) => code,
+            Err(e) => return Ok(DecodeResult::Error(e, idx)),
+        };
         match code {
             Code::Literal(x) => {
-                window[idx] = x;
+                out[idx] = x;
                 idx += 1;
             }
             Code::Dictionary { distance, length } => {
@@ -44,48 +91,58 @@ pub fn decode<B: BitRead>(
                 let mut length = length as usize;
 
                 if distance > idx {
-                    return Err(Error::DistanceTooMuch);
+                    // reaches back into the history
+                    let back = distance - idx;
+                    if back > history.len() {
+                        return Err(Error::DistanceTooMuch);
+                    }
+                    let begin = history.len() - back;
+                    let n = back.min(length);
+                    out[idx..idx + n].copy_from_slice(&history[begin..begin + n]);
+                    idx += n;
+                    length -= n;
                 }
 
-                let begin = idx - distance;
+                let begin = idx.saturating_sub(distance); // unused if no length is left
                 while length > 0 {
                     let n = distance.min(length);
-                    window.copy_within(begin..begin + n, idx);
+                    out.copy_within(begin..begin + n, idx);
                     idx += n;
                     length -= n;
                     distance += n;
                 }
             }
             Code::EndOfBlock => {
-                return Ok(DecodeResult::Done(idx - boundary));
+                return Ok(DecodeResult::Done(idx));
             }
         }
 
-        if idx + MAX_LENGTH as usize >= window.len() {
-            return Ok(DecodeResult::WindowIsFull(idx - boundary));
+        if idx + MAX_LENGTH as usize >= out.len() {
+            return Ok(DecodeResult::WindowIsFull(idx));
         }
     }
 }
 
 #[inline(always)]
-fn read_next_code<B: BitRead>(
+pub(crate) fn read_next_code<B: BitRead>(
     reader: &mut B,
     ll_decoder: &HuffmanDecoder,
     dist_decoder: &HuffmanDecoder,
 ) -> Result<Code> {
-    let bitcode = reader.peek_bits()?;
-    let (symbol, len) = ll_decoder.decode(bitcode)?;
-    reader.consume(len);
+    let symbol = ll_decoder.read(reader)?;
     match symbol.cmp(&END_OF_BLOCK) {
         Less => Ok(Code::Literal(symbol as u8)),
         Equal => Ok(Code::EndOfBlock),
         Greater => {
-            let (bits, mut length) = SYMBOL2BITS_LENGTH[(symbol & 0xFF) as usize];
+            // symbols 286, 287 and distance symbols 30, 31 never occur in valid data
+            let (bits, mut length) = *SYMBOL2BITS_LENGTH
+                .get((symbol & 0xFF) as usize)
+                .ok_or(Error::InvalidSymbol)?;
             length += reader.read_bits(bits)?;
-            let bitcode = reader.peek_bits()?;
-            let (symbol, len) = dist_decoder.decode(bitcode)?;
-            reader.consume(len);
-            let (bits, mut distance) = SYMBOL2BITS_DISTANCE[symbol as usize];
+            let symbol = dist_decoder.read(reader)?;
+            let (bits, mut distance) = *SYMBOL2BITS_DISTANCE
+                .get(symbol as usize)
+                .ok_or(Error::InvalidSymbol)?;
             distance += reader.read_bits(bits)?;
             Ok(Code::Dictionary {
                 distance: distance as u16,
@@ -0,0 +1,429 @@
+//! Parallel decompression of multi-member gzip files, e.g., concatenated or rotated `.gz` files
+//!
+//! Members are independent of one another, so they can be inflated concurrently.
+//! As the member boundaries are not known up front, every occurrence of the gzip magic bytes
+//! is a candidate to be inflated by the workers,
+//! and only the candidate found exactly at the end of the preceding member is accepted.
+//! A member too large to be buffered is inflated on the calling thread instead, as it is consumed.
+//! So is a member whose buffering would take the output past `Limits::max_output`,
+//! counting what has been produced and what the workers have buffered so far.
+
+use crate::error::{Context, Error, Result};
+use crate::producer::{check_trailing, Format, Limits, Options, Produce, Producer, Verifier};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// ID1, ID2 and CM of a gzip header
+const MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+/// # candidates dispatched to the workers but not yet consumed, per worker
+const IN_FLIGHT_PER_THREAD: usize = 2;
+/// members producing more than this many bytes are left to the calling thread
+const MAX_BUFFERED_OUTPUT: usize = 16 << 20;
+
+/// header, data and footer of a member, and # compressed bytes of it
+type Member = (Vec<Produce>, usize);
+type Inflated = (usize, Option<Result<Member>>); // candidate offset, None if too large
+
+enum State {
+    Load,
+    Members,
+    Inline(Box<Producer<Cursor<Arc<[u8]>>>>, Verifier),
+    Done,
+}
+
+/// Inflates gzip members concurrently with num_threads workers, producing them in order
+/// The whole compressed input is read into memory first
+pub struct ParallelMemberProducer<R: Read> {
+    read: Option<R>, // taken once the input is loaded
+    input: Arc<[u8]>,
+    num_threads: usize,
+    options: Options,
+    state: State,
+    job_tx: Option<SyncSender<usize>>,
+    inflated_rx: Option<Receiver<Inflated>>,
+    workers: Vec<JoinHandle<()>>,
+    budget: Option<Arc<AtomicU64>>, // # bytes left under max_output, less those buffered by the workers
+    dispatched: BTreeSet<usize>,    // candidates being inflated by the workers
+    inflated: BTreeMap<usize, Option<Result<Member>>>, // received ahead of the next member
+    max_in_flight: usize,
+    scan: usize,     // offset from which to look for the next candidate
+    position: usize, // offset of the next member
+    num_members: usize,
+    total_out: u64,
+    member_base: u64,         // # bytes produced before the current member
+    queue: VecDeque<Produce>, // rest of the member being produced
+}
+
+impl<R: Read> ParallelMemberProducer<R> {
+    pub fn new(read: R, num_threads: usize) -> Self {
+        Self::with_options(read, num_threads, Options::default())
+    }
+
+    /// the format is always gzip, and `recover` and `salvage` are not supported
+    pub fn with_options(read: R, num_threads: usize, options: Options) -> Self {
+        let num_threads = num_threads.max(1);
+        Self {
+            read: Some(read),
+            input: Arc::from(Vec::new()),
+            num_threads,
+            options: Options {
+                format: Format::Gzip,
+                ..options
+            },
+            state: State::Load,
+            job_tx: None,
+            inflated_rx: None,
+            workers: Vec::new(),
+            budget: options
+                .limits
+                .max_output
+                .map(|max| Arc::new(AtomicU64::new(max))),
+            dispatched: BTreeSet::new(),
+            inflated: BTreeMap::new(),
+            max_in_flight: num_threads * IN_FLIGHT_PER_THREAD,
+            scan: 0,
+            position: 0,
+            num_members: 0,
+            total_out: 0,
+            member_base: 0,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// read the whole input and spawn the workers
+    fn load(&mut self) -> Result<()> {
+        let mut input = Vec::new();
+        if let Some(mut read) = self.read.take() {
+            read.read_to_end(&mut input)?;
+        }
+        self.input = Arc::from(input);
+
+        let (job_tx, job_rx) = std::sync::mpsc::sync_channel::<usize>(self.max_in_flight);
+        let (inflated_tx, inflated_rx) = std::sync::mpsc::channel::<Inflated>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        self.workers = (0..self.num_threads)
+            .map(|_| {
+                let input = self.input.clone();
+                let options = self.options.clone();
+                let job_rx = job_rx.clone();
+                let inflated_tx = inflated_tx.clone();
+                let budget = self.budget.clone();
+                std::thread::spawn(move || {
+                    inflate_members(input, options, budget, job_rx, inflated_tx)
+                })
+            })
+            .collect();
+        self.job_tx = Some(job_tx);
+        self.inflated_rx = Some(inflated_rx);
+        Ok(())
+    }
+
+    /// hand over the candidates to the workers until enough are in flight
+    fn dispatch(&mut self) -> Result<()> {
+        while self.dispatched.len() + self.inflated.len() < self.max_in_flight {
+            self.scan = self.scan.max(self.position);
+            let Some(offset) = find_candidate(&self.input, self.scan) else {
+                break;
+            };
+            self.scan = offset + 1;
+            if is_large_member(&self.input, offset) {
+                // left to the calling thread
+                self.inflated.insert(offset, None);
+                continue;
+            }
+            let job_tx = self.job_tx.as_ref().unwrap();
+            job_tx
+                .send(offset)
+                .map_err(|_| std::io::Error::other("worker thread panicked"))?;
+            self.dispatched.insert(offset);
+        }
+        Ok(())
+    }
+
+    /// wait for a candidate to be inflated by the workers
+    fn receive(&mut self) -> Result<()> {
+        let inflated_rx = self.inflated_rx.as_ref().unwrap();
+        let (offset, inflated) = inflated_rx
+            .recv()
+            .map_err(|_| std::io::Error::other("worker thread panicked"))?;
+        self.dispatched.remove(&offset);
+        if offset >= self.position {
+            self.inflated.insert(offset, inflated);
+        } else {
+            self.release(&inflated);
+        }
+        Ok(())
+    }
+
+    /// drop the candidates before the position, which turned out to be false
+    fn discard_before(&mut self, position: usize) {
+        let rest = self.inflated.split_off(&position);
+        for inflated in std::mem::replace(&mut self.inflated, rest).into_values() {
+            self.release(&inflated);
+        }
+    }
+
+    /// return the bytes buffered for a candidate to the budget
+    fn release(&self, inflated: &Option<Result<Member>>) {
+        if let (Some(budget), Some(Ok((produces, _)))) = (&self.budget, inflated) {
+            budget.fetch_add(output_len(produces), Ordering::Relaxed);
+        }
+    }
+
+    fn next_helper(&mut self) -> Result<Option<Produce>> {
+        if let Some(produce) = self.queue.pop_front() {
+            self.check_output(&produce)?;
+            return Ok(Some(produce));
+        }
+        let produce = match &mut self.state {
+            State::Load => {
+                self.load()?;
+                self.state = State::Members;
+                return self.next_helper();
+            }
+            State::Members => {
+                if self.position == self.input.len() {
+                    if self.num_members == 0 {
+                        return Err(Error::EmptyInput);
+                    }
+                    self.state = State::Done;
+                    return Ok(Some(Produce::End(self.position as u64)));
+                }
+                if self.num_members > 0 {
+                    let rest = &self.input[self.position..];
+                    if let Some(policy) = check_trailing(rest, self.options.trailing)? {
+                        let len = rest.len();
+                        self.position = self.input.len();
+                        self.state = State::Done;
+                        self.queue.push_back(Produce::End(self.position as u64));
+                        return Ok(Some(Produce::Trailing(policy, len as u64)));
+                    }
+                }
+                self.options
+                    .limits
+                    .check_members(self.num_members as u64 + 1)?;
+                self.num_members += 1;
+                self.member_base = self.total_out;
+                self.dispatch()?;
+                while self.dispatched.contains(&self.position) {
+                    self.receive()?;
+                }
+                match self.inflated.remove(&self.position) {
+                    Some(Some(member)) => {
+                        let (produces, len) = member.map_err(|e| self.rebase(e))?;
+                        self.queue.extend(produces);
+                        self.position += len;
+                        // the rest of the candidates before it were false
+                        self.discard_before(self.position);
+                    }
+                    Some(None) | None => {
+                        // too large to buffer, or not even a candidate
+                        let mut read = Cursor::new(self.input.clone());
+                        read.set_position(self.position as u64);
+                        let producer = Producer::with_options(read, self.options.clone());
+                        let producer = Box::new(producer);
+                        self.state = State::Inline(producer, Verifier::new(Format::Gzip));
+                    }
+                }
+                return self.next_helper();
+            }
+            State::Inline(producer, verifier) => {
+                let produce = match producer.next() {
+                    Some(Produce::Err(e)) => return Err(self.rebase(e)),
+                    Some(produce) => produce,
+                    None => {
+                        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+                    }
+                };
+                if let Err(e) = verifier.verify(&produce) {
+                    let e = e.with_context(producer.context());
+                    return Err(self.rebase(e));
+                }
+                if let (Produce::Data(xs), Some(budget)) = (&produce, &self.budget) {
+                    // not buffered beforehand, unlike the members from the workers
+                    let len = xs.len() as u64;
+                    let _ = budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                        Some(left.saturating_sub(len))
+                    });
+                }
+                if let Produce::Footer(_) = produce {
+                    self.position += (**producer).position() as usize;
+                    self.discard_before(self.position);
+                    self.state = State::Members;
+                }
+                produce
+            }
+            State::Done => return Ok(None),
+        };
+        self.check_output(&produce)?;
+        Ok(Some(produce))
+    }
+
+    /// attach the context of the whole input to an error of the current member
+    fn rebase(&self, e: Error) -> Error {
+        e.rebase(self.num_members, self.position as u64 * 8, self.member_base)
+    }
+
+    /// check the total output against the limit
+    /// the rest of the limits apply to each member, and are checked by its producer
+    fn check_output(&mut self, produce: &Produce) -> Result<()> {
+        if let Produce::Data(xs) = produce {
+            self.total_out += xs.len() as u64;
+            let limits = Limits {
+                max_output: self.options.limits.max_output,
+                ..Limits::default()
+            };
+            limits.check_output(self.total_out, 0, 0)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ParallelMemberProducer<R> {
+    type Item = Produce;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_helper().unwrap_or_else(|e| {
+            self.state = State::Done;
+            self.queue.clear();
+            let e = match e.context() {
+                Some(_) => e, // from the producer of the member
+                None => e.with_context(Context {
+                    member: self.num_members,
+                    bit_position: Some(self.position as u64 * 8),
+                    output_position: self.total_out,
+                    block: None,
+                }),
+            };
+            Some(Produce::Err(e))
+        })
+    }
+}
+
+impl<R: Read> Drop for ParallelMemberProducer<R> {
+    fn drop(&mut self) {
+        // let the workers know there is no more candidate, and nobody to receive
+        self.job_tx = None;
+        self.inflated_rx = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// offset of the first occurrence of the gzip magic bytes at or after from
+fn find_candidate(input: &[u8], from: usize) -> Option<usize> {
+    input
+        .get(from..)?
+        .windows(MAGIC.len() + 1)
+        .position(|xs| xs[..MAGIC.len()] == MAGIC && xs[MAGIC.len()] & 0xE0 == 0) // reserved flags
+        .map(|idx| from + idx)
+}
+
+/// whether the member at the offset appears to produce more than MAX_BUFFERED_OUTPUT bytes
+/// judging from ISIZE, assuming the member ends right before the next candidate
+fn is_large_member(input: &[u8], offset: usize) -> bool {
+    let end = find_candidate(input, offset + 1).unwrap_or(input.len());
+    let size = match input.get(end.saturating_sub(4)..end) {
+        Some(bytes) if end >= offset + 4 => u32::from_le_bytes(bytes.try_into().unwrap()),
+        _ => 0,
+    };
+    size as usize > MAX_BUFFERED_OUTPUT
+}
+
+fn inflate_members(
+    input: Arc<[u8]>,
+    options: Options,
+    budget: Option<Arc<AtomicU64>>,
+    job_rx: Arc<Mutex<Receiver<usize>>>,
+    inflated_tx: Sender<Inflated>,
+) {
+    loop {
+        let job = job_rx.lock().unwrap().recv();
+        let Ok(offset) = job else {
+            return; // no more candidate
+        };
+        let inflated = inflate_member(&input[offset..], options.clone(), budget.as_deref());
+        if inflated_tx.send((offset, inflated)).is_err() {
+            return; // producer is gone
+        }
+    }
+}
+
+/// inflate and verify the gzip member at the beginning of data
+/// returns None if it produces more than MAX_BUFFERED_OUTPUT bytes, or more than the budget left,
+/// out of which the bytes buffered are taken unless an error is returned
+fn inflate_member(
+    data: &[u8],
+    options: Options,
+    budget: Option<&AtomicU64>,
+) -> Option<Result<Member>> {
+    let mut size = 0;
+    let inflated = inflate_member_helper(data, options, budget, &mut size);
+    if let (Some(budget), None | Some(Err(_))) = (budget, &inflated) {
+        budget.fetch_add(size as u64, Ordering::Relaxed);
+    }
+    inflated
+}
+
+/// see `inflate_member()`, where size is # bytes taken out of the budget
+fn inflate_member_helper(
+    data: &[u8],
+    options: Options,
+    budget: Option<&AtomicU64>,
+    size: &mut usize,
+) -> Option<Result<Member>> {
+    let mut producer = Producer::with_options(data, options);
+    let mut verifier = Verifier::new(Format::Gzip);
+    let mut produces = Vec::new();
+    for produce in &mut producer {
+        if let Err(e) = verifier.verify(&produce) {
+            return Some(Err(e.with_context(producer.context())));
+        }
+        match produce {
+            Produce::Data(xs) => {
+                if *size + xs.len() > MAX_BUFFERED_OUTPUT || !take(budget, xs.len()) {
+                    return None;
+                }
+                *size += xs.len();
+                produces.push(Produce::Data(xs));
+            }
+            Produce::Footer(footer) => {
+                produces.push(Produce::Footer(footer));
+                return Some(Ok((produces, producer.position() as usize)));
+            }
+            Produce::Err(e) => return Some(Err(e)),
+            produce => produces.push(produce),
+        }
+    }
+    Some(Err(
+        std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+    ))
+}
+
+/// take n bytes out of the budget if there are enough left
+fn take(budget: Option<&AtomicU64>, n: usize) -> bool {
+    let Some(budget) = budget else {
+        return true;
+    };
+    budget
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            left.checked_sub(n as u64)
+        })
+        .is_ok()
+}
+
+/// # bytes of the data of a member
+fn output_len(produces: &[Produce]) -> u64 {
+    produces
+        .iter()
+        .map(|produce| match produce {
+            Produce::Data(xs) => xs.len() as u64,
+            _ => 0,
+        })
+        .sum()
+}
@@ -1,123 +1,501 @@
-use crate::bitread::{BitRead, BitReader};
-use crate::codebook::CodeBook;
-use crate::error::{Error, Result};
+use crate::bitread::{BitRead, BitReader, SliceBitReader};
+use crate::checksum::{Adler32Checksum, Checksum, Crc32Checksum};
+use crate::codebook::{CodeBook, CODE_LENGTH_ORDER};
+use crate::error::{Context, Error, Result};
 use crate::footer::Footer;
-use crate::header::Header;
+use crate::header::{Header, HeaderOptions};
 use crate::huffman_decoder::HuffmanDecoder;
-use crate::lz77::{decode, DecodeResult, MAX_DISTANCE};
+use crate::lz77::{
+    decode_into, decode_into_exact, read_next_code, Code, DecodeResult, MAX_DISTANCE,
+};
 use crate::sliding_window::SlidingWindow;
-use std::io::Read;
+use crate::speculative::is_block_header;
+use crate::zlib::{ZlibFooter, ZlibHeader};
+use std::io::{ErrorKind, Read, Seek};
+use std::ops::Range;
 
 enum State {
     Header,
     Block,
+    Stored(usize, bool), // # bytes left in the block and whether it is the final block
     Inflate(bool),
     Footer,
+    End,
+    Truncated, // the output decodable before the end of the input has been produced in salvage mode
+    Done,
 }
 
+/// container wrapping the deflate stream
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    /// RFC 1952, possibly with multiple members
+    #[default]
+    Gzip,
+    /// RFC 1950
+    Zlib,
+    /// RFC 1951 without any container, ending at the final block
+    Raw,
+}
+
+/// type of a deflate block
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+/// What to do with the bytes following the last gzip member that do not form another member
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TrailingPolicy {
+    /// fail as if they were an invalid member
+    #[default]
+    Error,
+    /// ignore zero padding, e.g., of tape archives, but fail on anything else
+    IgnoreZeros,
+    /// ignore anything not beginning with the gzip magic bytes, along with the rest of the input,
+    /// as GNU gzip does with its "trailing garbage ignored" warning
+    IgnoreGarbage,
+    /// ignore everything after the first member, even if it forms another member
+    IgnoreAfterFirstMember,
+}
+
+/// Safeguards against decompression bombs, i.e., tiny inputs inflating to huge outputs
+/// Each is unlimited if None
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// maximum # bytes of the output in total
+    pub max_output: Option<u64>,
+    /// maximum # bytes of the output of each gzip member
+    pub max_member_output: Option<u64>,
+    /// maximum ratio of the output to the input consumed so far,
+    /// checked once the output exceeds RATIO_GRACE bytes
+    pub max_ratio: Option<u64>,
+    /// maximum # gzip members
+    pub max_members: Option<u64>,
+}
+
+/// # bytes of the output below which the ratio is not checked,
+/// as it is dominated by the headers for small inputs
+pub const RATIO_GRACE: u64 = 1 << 20;
+/// # bytes inflated at most before checking the ratio
+const RATIO_CHECK_INTERVAL: usize = 1 << 20;
+
+impl Limits {
+    /// check the output against the limits, given # bytes in total, of the current member,
+    /// and # input bytes consumed so far
+    pub fn check_output(&self, total_out: u64, member_out: u64, total_in: u64) -> Result<()> {
+        if self.max_output.is_some_and(|max| total_out > max) {
+            return Err(Error::OutputLimitExceeded);
+        }
+        if self.max_member_output.is_some_and(|max| member_out > max) {
+            return Err(Error::MemberOutputLimitExceeded);
+        }
+        if self
+            .max_ratio
+            .is_some_and(|max| total_out > RATIO_GRACE && total_out > max.saturating_mul(total_in))
+        {
+            return Err(Error::RatioLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// check # members against the limit, including the one about to be read
+    pub fn check_members(&self, num_members: u64) -> Result<()> {
+        if self.max_members.is_some_and(|max| num_members > max) {
+            return Err(Error::MemberLimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// # bytes that may be inflated at once, such that exceeding the limits is caught early
+    pub(crate) fn allowance(&self, total_out: u64, member_out: u64) -> usize {
+        let output = self.max_output.map(|max| max.saturating_sub(total_out));
+        let member = self
+            .max_member_output
+            .map(|max| max.saturating_sub(member_out));
+        let ratio = self.max_ratio.map(|_| RATIO_CHECK_INTERVAL as u64);
+        [output, member, ratio]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(usize::MAX, |n| {
+                n.saturating_add(1).try_into().unwrap_or(usize::MAX)
+            })
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Options {
+    pub format: Format,
+    /// preset dictionary for zlib streams with FDICT set
+    pub dictionary: Option<Vec<u8>>,
+    pub header: HeaderOptions,
+    /// stop after the first gzip member, leaving the rest of the input unconsumed
+    pub single_member: bool,
+    /// applies to gzip streams only
+    pub trailing: TrailingPolicy,
+    pub limits: Limits,
+    /// report damaged input by `Produce::Damaged` rather than failing,
+    /// skipping it up to the next plausible gzip member or deflate block header
+    /// a fixed huffman block is plausible only if it decodes up to its end within the lookahead,
+    /// followed by another plausible block header, or by the footer and the next member if final
+    /// back-references into the history lost are filled with PLACEHOLDER
+    /// the producer verifies the checksums itself, and the damaged members are not verified
+    pub recover: bool,
+    /// on truncated input, produce the output decodable before the end of the input
+    /// and then fail with `Error::Truncated`, rather than dropping the last bits decoded
+    pub salvage: bool,
+}
+
+/// stands in for the bytes of the history lost in recovery mode
+pub const PLACEHOLDER: u8 = b'?';
+/// # bytes looked ahead when scanning for a header to resume at in recovery mode
+const RESYNC_LOOKAHEAD: usize = 1 << 17;
+/// # bytes at the end of the lookahead not scanned until more input is read,
+/// enough for a dynamic block header or a stored block
+const RESYNC_MARGIN: usize = (1 << 16) + (1 << 10);
+/// # fixed huffman blocks in a row decoded at most to tell whether the first is plausible
+const MAX_FIXED_BLOCKS: usize = 4;
+
 pub enum Produce {
     Header(Header),
     Footer(Footer),
+    ZlibHeader(ZlibHeader),
+    ZlibFooter(ZlibFooter),
     Data(Vec<u8>),
+    /// # trailing bytes ignored at the end of the input and the policy applied to them,
+    /// i.e., `IgnoreAfterFirstMember` if so configured,
+    /// and otherwise `IgnoreZeros` if they are all zeros and `IgnoreGarbage` if not
+    Trailing(TrailingPolicy, u64),
+    /// damaged input in recovery mode, with the error and the range of compressed bit positions,
+    /// i.e., skipped up to where inflating resumed after a decoding error,
+    /// or spanning the member whose checksum does not match
+    Damaged(Error, Range<u64>),
+    /// end of the stream with # input bytes consumed, including the partially consumed byte
+    End(u64),
     Err(Error),
 }
 
 pub struct Producer<R: Read> {
     reader: BitReader<R>,
+    options: Options,
     state: State,
     member_idx: usize,
+    total_out: u64,                  // # bytes produced so far
+    member_out: u64,                 // # bytes produced so far within the current member
+    num_blocks: u64,                 // # blocks begun within the current member
+    block: Option<(u64, BlockType)>, // index and type of the block being read, if any
     window: SlidingWindow,
     ll_decoder: HuffmanDecoder,
     dist_decoder: HuffmanDecoder,
+    spare: Vec<Vec<u8>>, // buffers returned by the consumer, to be reused for data
+    pending: Range<usize>, // inflated into the write buffer but not yet delivered by read_data()
+    damaged: Option<(Error, Range<u64>)>, // recovered from within read_data(), to be produced
+    deferred: Option<Error>, // decoding error in recovery mode, raised once the data before it is delivered
+    verifier: Option<Verifier>, // in recovery mode
+    member_start: u64,       // bit position of the current member
 }
 
+/// maximum # spare buffers kept for reuse
+const MAX_SPARE_BUFFERS: usize = 16;
+/// buffers smaller than this given to read_data() are filled through the window instead
+const MIN_DIRECT_BUFFER: usize = MAX_DISTANCE as usize;
+
 impl<R: Read> Producer<R> {
     pub fn new(read: R) -> Self {
+        Self::with_options(read, Options::default())
+    }
+
+    pub fn with_options(read: R, options: Options) -> Self {
         Self {
             reader: BitReader::new(read),
             state: State::Header,
             member_idx: 0,
+            total_out: 0,
+            member_out: 0,
+            num_blocks: 0,
+            block: None,
             window: SlidingWindow::new(),
             ll_decoder: HuffmanDecoder::uninitialized(),
             dist_decoder: HuffmanDecoder::uninitialized(),
+            spare: Vec::new(),
+            pending: 0..0,
+            damaged: None,
+            deferred: None,
+            verifier: options.recover.then(|| Verifier::new(options.format)),
+            member_start: 0,
+            options,
+        }
+    }
+
+    /// hand back the buffer of a consumed `Produce::Data` to be reused
+    pub fn recycle(&mut self, mut buf: Vec<u8>) {
+        if self.spare.len() < MAX_SPARE_BUFFERS {
+            buf.clear();
+            self.spare.push(buf);
         }
     }
 
+    /// an empty buffer, reusing a recycled one if any
+    fn new_buffer(&mut self) -> Vec<u8> {
+        self.spare.pop().unwrap_or_default()
+    }
+
+    /// # input bytes consumed so far, including the partially consumed byte
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// # input bits consumed so far
+    pub fn bit_position(&self) -> u64 {
+        self.reader.bit_position()
+    }
+
+    /// # bytes produced so far
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// whether the next item will begin with a new deflate block
+    /// inflating can be resumed from here given the bit position and the history
+    pub fn is_at_block_boundary(&self) -> bool {
+        matches!(self.state, State::Block)
+    }
+
+    /// the underlying reader, along with the bytes read ahead from it but not yet consumed,
+    /// e.g., what follows the gzip stream in a container, once `Produce::End` is reached
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        self.reader.into_inner()
+    }
+
+    /// up to 32kB of the most recently produced data within the current member
+    pub fn history(&self) -> &[u8] {
+        self.window.history()
+    }
+
     fn next_helper(&mut self) -> Result<Option<Produce>> {
+        if let Some((e, bits)) = self.damaged.take() {
+            return Ok(Some(Produce::Damaged(e, bits)));
+        }
+        if !self.pending.is_empty() {
+            // the rest of what read_data() has inflated
+            let mut buf = self.new_buffer();
+            buf.extend_from_slice(&self.window.write_buffer()[self.pending.clone()]);
+            self.window.slide(self.pending.end);
+            self.pending = 0..0;
+            return Ok(Some(Produce::Data(buf)));
+        }
+        if let Some(e) = self.deferred.take() {
+            return Err(e);
+        }
         let produce = match self.state {
             State::Header => {
                 if !self.reader.has_data_left()? {
-                    return if self.member_idx == 0 {
-                        Err(Error::EmptyInput)
-                    } else {
-                        Ok(None)
-                    };
+                    if self.member_idx == 0 {
+                        return Err(Error::EmptyInput);
+                    }
+                    self.state = State::Done;
+                    return Ok(Some(Produce::End(self.reader.position())));
+                }
+                if self.member_idx > 0 && self.options.format == Format::Gzip {
+                    if let Some(produce) = self.skip_trailing()? {
+                        self.state = State::End;
+                        return Ok(Some(produce));
+                    }
                 }
+                self.options
+                    .limits
+                    .check_members(self.member_idx as u64 + 1)?;
+                self.member_start = self.reader.bit_position();
                 self.state = State::Block;
                 self.member_idx += 1;
-                Produce::Header(Header::read(&mut self.reader)?)
-            }
-            State::Block => {
-                let header = self.reader.read_bits(3)?;
-                let is_final = header & 1 == 1;
-                match header & 0b110 {
-                    0b000 => {
-                        if is_final {
-                            self.state = State::Footer;
+                self.member_out = 0;
+                self.num_blocks = 0;
+                match self.options.format {
+                    Format::Gzip => Produce::Header(Header::read_with_options(
+                        &mut self.reader,
+                        &self.options.header,
+                    )?),
+                    Format::Zlib => {
+                        let header = ZlibHeader::read(&mut self.reader)?;
+                        if let Some(dictid) = header.dictid {
+                            set_dictionary(&mut self.window, &self.options, dictid)?;
                         }
-                        self.inflate_block0()?
-                    }
-                    0b010 => {
-                        self.ll_decoder = HuffmanDecoder::new(CodeBook::default_ll());
-                        self.dist_decoder = HuffmanDecoder::new(CodeBook::default_dist());
-                        self.state = State::Inflate(is_final);
-                        self.inflate(is_final)?
-                    }
-                    0b100 => {
-                        (self.ll_decoder, self.dist_decoder) = self.read_dynamic_codebooks()?;
-                        self.state = State::Inflate(is_final);
-                        self.inflate(is_final)?
+                        Produce::ZlibHeader(header)
                     }
-                    _ => return Err(Error::InvalidBlockType),
+                    Format::Raw => return self.next_helper(),
                 }
             }
+            State::Block => {
+                self.read_block_header()?;
+                return self.next_helper();
+            }
+            State::Stored(len, is_final) => self.inflate_block0(len, is_final)?,
             State::Inflate(is_final) => self.inflate(is_final)?,
-            State::Footer => {
-                self.state = State::Header;
-                self.window = SlidingWindow::new(); // reset history
-                Produce::Footer(Footer::read(&mut self.reader)?)
+            State::Footer => match self.options.format {
+                Format::Gzip => {
+                    self.state = if self.options.single_member {
+                        State::End
+                    } else {
+                        State::Header
+                    };
+                    self.window = SlidingWindow::new(); // reset history
+                    Produce::Footer(Footer::read(&mut self.reader)?)
+                }
+                Format::Zlib => {
+                    self.state = State::End; // zlib stream has a single member
+                    Produce::ZlibFooter(ZlibFooter::read(&mut self.reader)?)
+                }
+                Format::Raw => {
+                    self.state = State::Done;
+                    Produce::End(self.reader.position())
+                }
+            },
+            State::End => {
+                self.state = State::Done;
+                Produce::End(self.reader.position())
             }
+            State::Truncated => {
+                return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+            }
+            State::Done => return Ok(None),
         };
         Ok(Some(produce))
     }
 
-    fn inflate_block0(&mut self) -> Result<Produce> {
+    /// consume the rest of the input if it is to be ignored rather than read as another member
+    fn skip_trailing(&mut self) -> Result<Option<Produce>> {
+        let policy = self.options.trailing;
+        let is_member = self.reader.peek_bits()? & 0xFFFFFF == GZIP_MAGIC;
+        if policy == TrailingPolicy::Error
+            || (is_member && policy != TrailingPolicy::IgnoreAfterFirstMember)
+        {
+            return Ok(None);
+        }
+        let mut buf = [0; 4096];
+        let mut n = 0;
+        let mut is_zero = true;
+        loop {
+            let m = self.reader.read(&mut buf)?;
+            if m == 0 {
+                break;
+            }
+            is_zero &= buf[..m].iter().all(|x| *x == 0);
+            if !is_zero && policy == TrailingPolicy::IgnoreZeros {
+                return Err(Error::InvalidGzHeader);
+            }
+            n += m as u64;
+        }
+        Ok(Some(Produce::Trailing(applied_policy(policy, is_zero), n)))
+    }
+
+    /// read the header of the next block, and its codebooks if any
+    fn read_block_header(&mut self) -> Result<()> {
+        let header = self.reader.read_bits(3)?;
+        let is_final = header & 1 == 1;
+        let block_type = match header & 0b110 {
+            0b000 => BlockType::Stored,
+            0b010 => BlockType::Fixed,
+            0b100 => BlockType::Dynamic,
+            _ => return Err(Error::InvalidBlockType),
+        };
+        self.block = Some((self.num_blocks, block_type));
+        self.num_blocks += 1;
+        match block_type {
+            BlockType::Stored => {
+                self.state = State::Stored(self.read_block0_len()?, is_final);
+            }
+            BlockType::Fixed => {
+                self.ll_decoder = HuffmanDecoder::new(CodeBook::default_ll());
+                self.dist_decoder = HuffmanDecoder::new(CodeBook::default_dist());
+                self.state = State::Inflate(is_final);
+            }
+            BlockType::Dynamic => {
+                (self.ll_decoder, self.dist_decoder) = read_dynamic_codebooks(&mut self.reader)?;
+                self.state = State::Inflate(is_final);
+            }
+        }
+        Ok(())
+    }
+
+    /// read LEN and NLEN of a block type 0
+    fn read_block0_len(&mut self) -> Result<usize> {
         self.reader.byte_align();
         let len = self.reader.read_bits(16)?;
         let nlen = self.reader.read_bits(16)?;
         if len ^ nlen != 0xFFFF {
             Err(Error::BlockType0LenMismatch)
         } else {
-            let len = len as usize;
-            let mut buf = vec![0; len];
-            self.reader.read_exact(&mut buf)?;
-            let n = len.min(MAX_DISTANCE as usize); // maximum history required
-            self.window.write_buffer()[..n].copy_from_slice(&buf[len - n..len]);
-            self.window.slide(n);
-            Ok(Produce::Data(buf))
+            Ok(len as usize)
         }
     }
 
+    fn inflate_block0(&mut self, len: usize, is_final: bool) -> Result<Produce> {
+        let mut buf = self.new_buffer();
+        buf.resize(len, 0);
+        let n = self.read_block0(&mut buf, len, is_final)?;
+        buf.truncate(n);
+        Ok(Produce::Data(buf))
+    }
+
+    /// read buf.len() out of len bytes left in the block type 0
+    /// returns # bytes read, which is fewer only if the input ends first in salvage mode
+    fn read_block0(&mut self, buf: &mut [u8], len: usize, is_final: bool) -> Result<usize> {
+        let n = if self.options.salvage {
+            read_up_to(&mut self.reader, buf)?
+        } else {
+            self.reader.read_exact(buf)?;
+            buf.len()
+        };
+        self.window.extend_history(&buf[..n]);
+        self.produced(n)?;
+        if n < buf.len() {
+            self.state = State::Truncated;
+            return Ok(n);
+        }
+        if len == buf.len() {
+            self.block = None;
+        }
+        self.state = match len - buf.len() {
+            0 if is_final => State::Footer,
+            0 => State::Block,
+            left => State::Stored(left, is_final),
+        };
+        Ok(n)
+    }
+
     fn inflate(&mut self, is_final: bool) -> Result<Produce> {
+        let n = self.inflate_window(is_final)?;
+        let mut buf = self.new_buffer();
+        buf.extend_from_slice(&self.window.write_buffer()[..n]);
+        self.window.slide(n);
+        Ok(Produce::Data(buf))
+    }
+
+    /// inflate into the write buffer of the window without sliding it
+    fn inflate_window(&mut self, is_final: bool) -> Result<usize> {
+        let decode = match self.options.salvage {
+            true => decode_into_exact,
+            false => decode_into,
+        };
         let boundary = self.window.boundary();
-        let n = match decode(
-            self.window.buffer(),
-            boundary,
+        let (history, out) = self.window.buffer().split_at_mut(boundary);
+        let result = decode(
+            out,
+            history,
             &mut self.reader,
             &self.ll_decoder,
             &self.dist_decoder,
-        )? {
+        )?;
+        self.inflated(result, is_final)
+    }
+
+    /// update the state after decoding
+    fn inflated(&mut self, result: DecodeResult, is_final: bool) -> Result<usize> {
+        let n = match result {
             DecodeResult::Done(n) => {
+                self.block = None;
                 self.state = if is_final {
                     State::Footer
                 } else {
@@ -126,80 +504,641 @@ impl<R: Read> Producer<R> {
                 n
             }
             DecodeResult::WindowIsFull(n) => n,
-            DecodeResult::Error(e) => {
+            DecodeResult::Error(e, n)
+                if self.options.salvage && is_truncation(&e, &self.reader) =>
+            {
+                // the error is to follow the output decodable before the end of the input
+                self.state = State::Truncated;
+                n
+            }
+            DecodeResult::Error(e, n)
+                if self.options.recover && n > 0 && !self.reader.is_overrun() =>
+            {
+                // the output decoded before the damage is delivered first
+                self.deferred = Some(e);
+                n
+            }
+            DecodeResult::Error(e, _) => {
                 return Err(e);
             }
         };
-        let buf = Vec::from(&self.window.write_buffer()[..n]);
-        self.window.slide(n);
+        if self.reader.is_overrun() && !matches!(self.state, State::Truncated) {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        self.produced(n)?;
+        Ok(n)
+    }
 
-        Ok(Produce::Data(buf))
+    /// account for n bytes produced, checking them against the limits
+    fn produced(&mut self, n: usize) -> Result<()> {
+        self.total_out += n as u64;
+        self.member_out += n as u64;
+        let total_in = self.reader.position();
+        let limits = &self.options.limits;
+        limits.check_output(self.total_out, self.member_out, total_in)
     }
 
-    fn read_dynamic_codebooks(&mut self) -> Result<(HuffmanDecoder, HuffmanDecoder)> {
-        let hlit = self.reader.read_bits(5)? as usize + 257;
-        let hdist = self.reader.read_bits(5)? as usize + 1;
-        let hclen = self.reader.read_bits(4)? as usize + 4;
-        let mut cl_lengths = [0; 19];
-        for idx in [
-            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
-        ]
-        .into_iter()
-        .take(hclen)
-        {
-            cl_lengths[idx] = self.reader.read_bits(3)?;
+    /// Inflate the data directly into buf, rather than into a new buffer as `next()` does
+    /// Only the 32kB history is kept internally
+    /// Returns 0 if buf is empty or the next item is not data, i.e., header, footer, end
+    /// or damage, in which case it is to be obtained from `next()`
+    pub fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.read_data_helper(buf) {
+            Ok(n) => {
+                if let Some(verifier) = &mut self.verifier {
+                    verifier.update(&buf[..n]);
+                }
+                Ok(n)
+            }
+            Err(e) => match self.recover(e) {
+                Ok(damaged) => {
+                    self.damaged = Some(damaged);
+                    Ok(0)
+                }
+                Err(e) => {
+                    self.state = State::Done;
+                    Err(e)
+                }
+            },
+        }
+    }
+
+    /// where the producer is at
+    pub fn context(&self) -> Context {
+        Context {
+            member: self.member_idx,
+            bit_position: Some(self.reader.bit_position()),
+            output_position: self.total_out,
+            block: self.block,
         }
-        let cl_codes = CodeBook::new(&cl_lengths)?;
-        let cl_decoder = HuffmanDecoder::new(cl_codes);
+    }
 
-        // The code lengths contain LL codes and Distance codes as a single table
-        let num_codes = hlit + hdist;
-        let mut lengths = Vec::with_capacity(num_codes);
-        while lengths.len() < num_codes {
-            let (cl_code, len) = cl_decoder
-                .decode(self.reader.peek_bits()?)
-                .or(Err(Error::ReadDynamicCodebook))?;
-            self.reader.consume(len);
-            match cl_code {
-                0..=15 => {
-                    lengths.push(cl_code);
+    /// attach the context to the error
+    fn error(&self, e: Error) -> Error {
+        let e = if self.reader.is_overrun() {
+            // the error is due to decoding the zero-padded bits past the end of the input
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+        } else {
+            e
+        };
+        e.with_context(self.context())
+    }
+
+    /// skip the damaged input in recovery mode, returning the error and the bits skipped
+    /// returns the error back if it is not to be recovered from,
+    /// as `Error::Truncated` if it is due to the end of the input in salvage mode
+    fn recover(&mut self, e: Error) -> Result<(Error, Range<u64>)> {
+        let e = self.error(e);
+        if self.options.salvage && e.kind() == ErrorKind::UnexpectedEof {
+            self.reader.clear_overrun();
+            self.state = State::Truncated;
+            let e = Error::Truncated {
+                compressed_offset: self.reader.position(),
+                output_len: self.total_out,
+            };
+            return Err(e.with_context(self.context()));
+        }
+        if !self.options.recover || !is_damage(&e) {
+            return Err(e);
+        }
+        self.reader.clear_overrun();
+        let start = self.reader.bit_position();
+        if let Err(e) = self.resync() {
+            return Err(self.error(e));
+        }
+        Ok((e, start..self.reader.bit_position()))
+    }
+
+    /// verify the item in recovery mode, reporting a checksum mismatch as damage to the member
+    fn verify(&mut self, produce: Option<Produce>) -> Option<Produce> {
+        let (Some(verifier), Some(item)) = (&mut self.verifier, &produce) else {
+            return produce;
+        };
+        let Err(e) = verifier.verify(item) else {
+            return produce;
+        };
+        let bits = self.member_start..self.reader.bit_position();
+        let damaged = Produce::Damaged(e.with_context(self.context()), bits);
+        if let Some(verifier) = &mut self.verifier {
+            // so that the checksum starts over with the next member
+            let _ = verifier.verify(&damaged);
+        }
+        Some(damaged)
+    }
+
+    /// scan forward bit by bit, past the current bit, for a plausible gzip member header
+    /// or deflate block header to resume at, or else up to the end of the input
+    fn resync(&mut self) -> Result<()> {
+        let format = self.options.format;
+        self.pending = 0..0;
+        self.block = None;
+        let mut fixed = FixedBlockScanner::new();
+        let mut from = 1; // # bits to skip at least, so as to make progress
+        loop {
+            let nbits = self.reader.bit_position() % 8;
+            let input = self.reader.lookahead(RESYNC_LOOKAHEAD)?;
+            let is_eof = input.len() < RESYNC_LOOKAHEAD;
+            let to = match is_eof {
+                true => input.len() as u64 * 8,
+                false => (input.len() - RESYNC_MARGIN) as u64 * 8,
+            };
+            fixed.reset(input.len());
+            let found = ((nbits + from).min(to)..to).find_map(|bit| {
+                resync_point(input, bit, format, &mut fixed).map(|state| (bit, state))
+            });
+            let Some((bit, state)) = found else {
+                self.reader.consume((to - nbits) as u32);
+                if is_eof {
+                    self.state = State::End;
+                    return Ok(());
                 }
-                16 if !lengths.is_empty() => {
-                    let length = 3 + self.reader.read_bits(2)? as usize;
-                    let x = *lengths.last().unwrap();
-                    lengths.resize(lengths.len() + length, x);
+                from = 0;
+                continue;
+            };
+            self.reader.consume((bit - nbits) as u32);
+            self.window = SlidingWindow::new();
+            if let State::Block = state {
+                // the history is lost
+                self.window
+                    .extend_history(&[PLACEHOLDER; MAX_DISTANCE as usize]);
+            }
+            self.state = state;
+            return Ok(());
+        }
+    }
+
+    fn read_data_helper(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.pending.is_empty() {
+            let n = buf.len().min(self.pending.len());
+            let begin = self.pending.start;
+            buf[..n].copy_from_slice(&self.window.write_buffer()[begin..begin + n]);
+            self.pending.start += n;
+            if self.pending.is_empty() {
+                self.window.slide(self.pending.end);
+                self.pending = 0..0;
+            }
+            return Ok(n);
+        }
+        if let Some(e) = self.deferred.take() {
+            return Err(e);
+        }
+        // inflate no more than the limits allow before checking them
+        let allowance = self
+            .options
+            .limits
+            .allowance(self.total_out, self.member_out);
+        let direct_len = buf.len().min(allowance);
+        loop {
+            let n = match self.state {
+                State::Block => {
+                    self.read_block_header()?;
+                    continue;
                 }
-                17 => {
-                    let length = 3 + self.reader.read_bits(3)? as usize;
-                    lengths.resize(lengths.len() + length, 0);
+                State::Stored(len, is_final) => {
+                    let n = len.min(buf.len());
+                    self.read_block0(&mut buf[..n], len, is_final)?
                 }
-                18 => {
-                    let length = 11 + self.reader.read_bits(7)? as usize;
-                    lengths.resize(lengths.len() + length, 0);
+                State::Inflate(is_final) if direct_len < MIN_DIRECT_BUFFER => {
+                    let n = self.inflate_window(is_final)?;
+                    self.pending = 0..n;
+                    return self.read_data_helper(buf);
                 }
-                _ => {
-                    unreachable!()
+                State::Inflate(is_final) => {
+                    let decode = match self.options.salvage {
+                        true => decode_into_exact,
+                        false => decode_into,
+                    };
+                    let result = decode(
+                        &mut buf[..direct_len],
+                        self.window.history(),
+                        &mut self.reader,
+                        &self.ll_decoder,
+                        &self.dist_decoder,
+                    )?;
+                    let n = self.inflated(result, is_final)?;
+                    self.window.extend_history(&buf[..n]);
+                    n
                 }
+                _ => return Ok(0),
+            };
+            if n > 0 {
+                return Ok(n);
             }
         }
+    }
+}
 
-        if lengths.len() != num_codes {
-            return Err(Error::ReadDynamicCodebook);
+impl<R: Read + Seek> Producer<R> {
+    /// restart from the beginning of the input
+    pub fn rewind(&mut self) -> Result<()> {
+        self.reader.seek(0)?;
+        self.state = State::Header;
+        self.member_idx = 0;
+        self.total_out = 0;
+        self.window = SlidingWindow::new();
+        Ok(())
+    }
+
+    /// resume inflating from a block boundary within a member,
+    /// given the bit position, the history preceding it and # bytes produced before it
+    /// as obtained at `is_at_block_boundary()`
+    pub fn resume(&mut self, bit_position: u64, history: &[u8], total_out: u64) -> Result<()> {
+        self.reader.seek(bit_position / 8)?;
+        let nbits = (bit_position % 8) as u32;
+        if nbits > 0 {
+            self.reader.read_bits(nbits)?;
         }
+        self.state = State::Block;
+        self.member_idx = self.member_idx.max(1);
+        self.total_out = total_out;
+        self.window = SlidingWindow::new();
+        self.window.extend_history(history);
+        Ok(())
+    }
+}
 
-        let ll_codes = CodeBook::new(&lengths[..hlit])?;
-        let dist_codes = CodeBook::new(&lengths[hlit..])?;
-        Ok((
-            HuffmanDecoder::new(ll_codes),
-            HuffmanDecoder::new(dist_codes),
-        ))
+/// Verifies the data against the checksum and size in the footers
+pub struct Verifier {
+    checksum: Option<Box<dyn Checksum + Send>>,
+    member_idx: usize,
+    total_out: u64,   // # bytes verified so far
+    is_damaged: bool, // whether damage has been reported within the current member
+}
+
+impl Verifier {
+    pub fn new(format: Format) -> Self {
+        let checksum: Option<Box<dyn Checksum + Send>> = match format {
+            Format::Gzip => Some(Box::new(Crc32Checksum::new())),
+            Format::Zlib => Some(Box::new(Adler32Checksum::new())),
+            Format::Raw => None,
+        };
+        Self {
+            checksum,
+            member_idx: 0,
+            total_out: 0,
+            is_damaged: false,
+        }
     }
+
+    /// update the checksum with data inflated by `Producer::read_data()`
+    pub fn update(&mut self, xs: &[u8]) {
+        self.total_out += xs.len() as u64;
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(xs);
+        }
+    }
+
+    /// update the checksum with data or check it against footer
+    pub fn verify(&mut self, produce: &Produce) -> Result<()> {
+        self.verify_helper(produce).map_err(|e| {
+            e.with_context(Context {
+                member: self.member_idx,
+                output_position: self.total_out,
+                ..Context::default()
+            })
+        })
+    }
+
+    fn verify_helper(&mut self, produce: &Produce) -> Result<()> {
+        match produce {
+            Produce::Header(_) | Produce::ZlibHeader(_) => self.member_idx += 1,
+            Produce::Data(xs) => self.total_out += xs.len() as u64,
+            _ => {}
+        }
+        let Some(checksum) = &mut self.checksum else {
+            return Ok(());
+        };
+        if self.is_damaged {
+            // the checksum of the damaged member is not verified, and starts over with the next
+            if let Produce::Header(_)
+            | Produce::ZlibHeader(_)
+            | Produce::Footer(_)
+            | Produce::ZlibFooter(_) = produce
+            {
+                self.is_damaged = false;
+                checksum.checksum();
+                checksum.reset_len();
+            }
+            return Ok(());
+        }
+        match produce {
+            Produce::Damaged(..) => self.is_damaged = true,
+            Produce::Data(xs) => checksum.update(xs),
+            Produce::Footer(footer) => {
+                if checksum.checksum() != footer.crc32 {
+                    return Err(Error::ChecksumMismatch);
+                }
+
+                if checksum.len() & 0xFFFFFFFF != footer.size as usize {
+                    return Err(Error::SizeMismatch);
+                }
+
+                checksum.reset_len();
+            }
+            Produce::ZlibFooter(footer) if checksum.checksum() != footer.adler32 => {
+                return Err(Error::ChecksumMismatch);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// ID1, ID2 and CM of a gzip header, as read in little endian
+const GZIP_MAGIC: u32 = 0x088B1F;
+
+/// whether the decoding error is due to the end of the input
+fn is_truncation<R: Read>(e: &Error, reader: &BitReader<R>) -> bool {
+    reader.is_overrun() || e.kind() == ErrorKind::UnexpectedEof
+}
+
+/// read into buf until it is full or the input ends, returning # bytes read
+fn read_up_to<R: Read>(read: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match read.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+/// whether the error is due to damaged input, which recovery mode skips,
+/// as opposed to the limits, the options or the underlying reader
+fn is_damage(e: &Error) -> bool {
+    match e.inner() {
+        Error::StdIoError(e) => e.kind() == ErrorKind::UnexpectedEof,
+        Error::EmptyInput
+        | Error::MissingDictionary
+        | Error::DictionaryMismatch
+        | Error::OutputLimitExceeded
+        | Error::MemberOutputLimitExceeded
+        | Error::RatioLimitExceeded
+        | Error::MemberLimitExceeded => false,
+        _ => true,
+    }
+}
+
+/// the state to resume at in recovery mode
+/// if a gzip member header or a deflate block header plausibly begins at the bit offset
+fn resync_point(
+    input: &[u8],
+    bit: u64,
+    format: Format,
+    fixed: &mut FixedBlockScanner,
+) -> Option<State> {
+    if format == Format::Gzip && is_member_header(input, bit) {
+        Some(State::Header)
+    } else if is_block_header(input, bit) || fixed.is_fixed_block(input, bit, format, 1) {
+        Some(State::Block)
+    } else {
+        None
+    }
+}
+
+/// whether a gzip member header plausibly begins at the bit offset
+fn is_member_header(input: &[u8], bit: u64) -> bool {
+    let begin = (bit / 8) as usize;
+    bit.is_multiple_of(8)
+        && input[begin..].starts_with(&GZIP_MAGIC.to_le_bytes()[..3])
+        && input.get(begin + 3).is_some_and(|flags| flags & 0xE0 == 0) // reserved flags
+}
+
+/// Tells whether fixed huffman blocks plausibly begin at the bit offsets of the input scanned
+/// Decoding from nearby offsets soon falls in step, so the offsets of the symbols
+/// that lead to an error are remembered to reject the later candidates reaching them
+struct FixedBlockScanner {
+    ll_decoder: HuffmanDecoder,
+    dist_decoder: HuffmanDecoder,
+    failing: Vec<bool>, // failing[bit]: decoding symbols from the bit offset runs into an error
+    visited: Vec<usize>, // bit offsets of the symbols decoded for the current candidate
+}
+
+impl FixedBlockScanner {
+    fn new() -> Self {
+        Self {
+            ll_decoder: HuffmanDecoder::new(CodeBook::default_ll()),
+            dist_decoder: HuffmanDecoder::new(CodeBook::default_dist()),
+            failing: Vec::new(),
+            visited: Vec::new(),
+        }
+    }
+
+    /// start over with new input of len bytes
+    fn reset(&mut self, len: usize) {
+        self.failing.clear();
+        self.failing.resize(len * 8, false);
+    }
+
+    /// whether a fixed huffman block plausibly begins at the bit offset, i.e., it decodes up to
+    /// its end of block within the input, followed by a plausible block header if not final,
+    /// or else by the footer and then the next gzip member or the end of the input
+    fn is_fixed_block(&mut self, input: &[u8], bit: u64, format: Format, depth: usize) -> bool {
+        let mut reader = SliceBitReader::new(input, bit as usize);
+        let Ok(header) = reader.read_bits(3) else {
+            return false;
+        };
+        if header & 0b110 != 0b010 {
+            return false;
+        }
+        let is_final = header & 1 == 1;
+        self.visited.clear();
+        let is_complete = loop {
+            let pos = reader.bit_position();
+            if self.failing.get(pos).is_none_or(|failing| *failing) {
+                break false;
+            }
+            self.visited.push(pos);
+            // the history lost is full of placeholders, so any distance is valid
+            match read_next_code(&mut reader, &self.ll_decoder, &self.dist_decoder) {
+                _ if reader.is_overrun() => break false,
+                Ok(Code::EndOfBlock) => break true,
+                Ok(_) => {}
+                Err(_) => break false,
+            }
+        };
+        let end = reader.bit_position() as u64;
+        let visited = std::mem::take(&mut self.visited);
+        let is_plausible = is_complete && self.is_followed(input, end, is_final, format, depth);
+        // deeper down, fewer blocks are left to follow, so only failures at the top are final
+        if !is_plausible && (!is_complete || depth == 1) {
+            for pos in &visited {
+                self.failing[*pos] = true;
+            }
+        }
+        self.visited = visited;
+        is_plausible
+    }
+
+    /// whether what follows the fixed huffman block ending at the bit offset is plausible
+    fn is_followed(
+        &mut self,
+        input: &[u8],
+        end: u64,
+        is_final: bool,
+        format: Format,
+        depth: usize,
+    ) -> bool {
+        if !is_final {
+            return is_block_header(input, end)
+                || (depth < MAX_FIXED_BLOCKS
+                    && self.is_fixed_block(input, end, format, depth + 1));
+        }
+        let footer_len = match format {
+            Format::Gzip => 8, // CRC32 and ISIZE
+            Format::Zlib => 4, // ADLER32
+            Format::Raw => 0,
+        };
+        let next = end.div_ceil(8) as usize + footer_len;
+        next == input.len() || (format == Format::Gzip && is_member_header(input, next as u64 * 8))
+    }
+}
+
+/// prime the history with the preset dictionary of the options, checked against dictid
+pub(crate) fn set_dictionary(
+    window: &mut SlidingWindow,
+    options: &Options,
+    dictid: u32,
+) -> Result<()> {
+    let dictionary = options
+        .dictionary
+        .as_ref()
+        .ok_or(Error::MissingDictionary)?;
+    let mut checksum = Adler32Checksum::new();
+    checksum.update(dictionary);
+    if checksum.checksum() != dictid {
+        return Err(Error::DictionaryMismatch);
+    }
+    window.extend_history(dictionary);
+    Ok(())
+}
+
+/// the policy applied to the trailing bytes ignored per the policy configured
+pub(crate) fn applied_policy(policy: TrailingPolicy, is_zero: bool) -> TrailingPolicy {
+    if policy == TrailingPolicy::IgnoreAfterFirstMember {
+        policy
+    } else if is_zero {
+        TrailingPolicy::IgnoreZeros
+    } else {
+        TrailingPolicy::IgnoreGarbage
+    }
+}
+
+/// whether the rest of the input following a member is to be ignored per the policy
+/// returns the policy applied if so, or None if it is to be read as another member
+pub(crate) fn check_trailing(
+    rest: &[u8],
+    policy: TrailingPolicy,
+) -> Result<Option<TrailingPolicy>> {
+    let is_member = rest.starts_with(&GZIP_MAGIC.to_le_bytes()[..3]);
+    if policy == TrailingPolicy::Error
+        || (is_member && policy != TrailingPolicy::IgnoreAfterFirstMember)
+    {
+        return Ok(None);
+    }
+    let is_zero = rest.iter().all(|x| *x == 0);
+    if !is_zero && policy == TrailingPolicy::IgnoreZeros {
+        return Err(Error::InvalidGzHeader);
+    }
+    Ok(Some(applied_policy(policy, is_zero)))
+}
+
+/// Inflate the first gzip member within data and verify its footer
+/// The decompressed data is appended to out
+/// Returns # bytes of the member
+pub fn inflate_member(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+    let mut producer = Producer::new(data);
+    let mut verifier = Verifier::new(Format::Gzip);
+    for produce in &mut producer {
+        if let Err(e) = verifier.verify(&produce) {
+            return Err(e.with_context(producer.context()));
+        }
+        match produce {
+            Produce::Data(xs) => out.extend_from_slice(&xs),
+            Produce::Footer(_) => return Ok(producer.position() as usize),
+            Produce::Err(e) => return Err(e),
+            _ => {}
+        }
+    }
+    let e: Error = std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into();
+    Err(e.with_context(producer.context()))
+}
+
+/// read the code lengths from a dynamic block header and build the decoders
+pub(crate) fn read_dynamic_codebooks<B: BitRead>(
+    reader: &mut B,
+) -> Result<(HuffmanDecoder, HuffmanDecoder)> {
+    let hlit = reader.read_bits(5)? as usize + 257;
+    let hdist = reader.read_bits(5)? as usize + 1;
+    let hclen = reader.read_bits(4)? as usize + 4;
+    let mut cl_lengths = [0; 19];
+    for idx in CODE_LENGTH_ORDER.into_iter().take(hclen) {
+        cl_lengths[idx] = reader.read_bits(3)?;
+    }
+    let cl_codes = CodeBook::new(&cl_lengths)?;
+    let cl_decoder = HuffmanDecoder::new(cl_codes);
+
+    // The code lengths contain LL codes and Distance codes as a single table
+    let num_codes = hlit + hdist;
+    let mut lengths = Vec::with_capacity(num_codes);
+    while lengths.len() < num_codes {
+        let cl_code = cl_decoder
+            .read(reader)
+            .or(Err(Error::ReadDynamicCodebook))?;
+        match cl_code {
+            0..=15 => {
+                lengths.push(cl_code);
+            }
+            16 if !lengths.is_empty() => {
+                let length = 3 + reader.read_bits(2)? as usize;
+                let x = *lengths.last().unwrap();
+                lengths.resize(lengths.len() + length, x);
+            }
+            17 => {
+                let length = 3 + reader.read_bits(3)? as usize;
+                lengths.resize(lengths.len() + length, 0);
+            }
+            18 => {
+                let length = 11 + reader.read_bits(7)? as usize;
+                lengths.resize(lengths.len() + length, 0);
+            }
+            _ => {
+                // 16 without any previous length to repeat
+                return Err(Error::ReadDynamicCodebook);
+            }
+        }
+    }
+
+    if lengths.len() != num_codes {
+        return Err(Error::ReadDynamicCodebook);
+    }
+
+    let ll_codes = CodeBook::new(&lengths[..hlit])?;
+    let dist_codes = CodeBook::new(&lengths[hlit..])?;
+    Ok((
+        HuffmanDecoder::new(ll_codes),
+        HuffmanDecoder::new(dist_codes),
+    ))
 }
 
 impl<R: Read> Iterator for Producer<R> {
     type Item = Produce;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_helper().unwrap_or_else(|e| Some(Produce::Err(e)))
+        let produce = self
+            .next_helper()
+            .unwrap_or_else(|e| match self.recover(e) {
+                Ok((e, bits)) => Some(Produce::Damaged(e, bits)),
+                Err(e) => {
+                    // nothing is to follow the error
+                    self.state = State::Done;
+                    Some(Produce::Err(e))
+                }
+            });
+        self.verify(produce)
     }
 }
@@ -0,0 +1,487 @@
+//! Sans-IO inflate, where the caller pushes the compressed input and pulls the decompressed data
+//!
+//! Nothing is read from a `Read`, so the decoder can be driven by the callbacks of an event loop
+//! or an async runtime. Each unit, i.e., a header, a block header with its codebooks, a symbol
+//! or a footer, is decoded from the input buffered so far. If the input turns out to be
+//! incomplete, the unit is rolled back and `Status::NeedInput` is returned, so that decoding
+//! resumes from the same bit position once more input is pushed.
+
+use crate::bitread::{BitRead, SliceBitReader};
+use crate::codebook::CodeBook;
+use crate::error::{Context, Error, Result};
+use crate::footer::Footer;
+use crate::header::Header;
+use crate::huffman_decoder::HuffmanDecoder;
+use crate::lz77::{decode_into_exact, DecodeResult, MAX_DISTANCE};
+use crate::producer::{
+    applied_policy, check_trailing, read_dynamic_codebooks, set_dictionary, BlockType, Format,
+    Options, Produce, TrailingPolicy, Verifier,
+};
+use crate::sliding_window::SlidingWindow;
+use crate::zlib::{ZlibFooter, ZlibHeader};
+use std::ops::Range;
+
+/// # bytes of ID1, ID2 and CM of a gzip header
+const GZIP_MAGIC_LEN: usize = 3;
+/// output buffers smaller than this are filled through the window instead
+const MIN_DIRECT_BUFFER: usize = MAX_DISTANCE as usize;
+
+enum State {
+    Header,
+    Block,
+    Stored(usize, bool), // # bytes left in the block and whether it is the final block
+    Inflate(bool),
+    Footer,
+    Trailing(bool, u64), // whether the trailing bytes are all zeros, and # bytes so far
+    End,
+    Done,
+}
+
+/// What `PushDecoder::pull()` has come up with
+pub enum Status {
+    /// # bytes written to the output
+    Data(usize),
+    /// header of the gzip member whose data is to follow
+    Header(Header),
+    /// all the input pushed so far is consumed; `push()` more or `finish()`
+    NeedInput,
+    /// # trailing bytes ignored at the end of the input and the policy applied to them,
+    /// as in `Produce::Trailing`
+    Trailing(TrailingPolicy, u64),
+    /// end of the stream with # input bytes consumed, including the partially consumed byte
+    End(u64),
+}
+
+/// What `inflate_symbols()` has stopped at
+enum Stop {
+    EndOfBlock,
+    Full,
+    NeedInput,
+}
+
+pub struct PushDecoder {
+    options: Options,
+    state: State,
+    input: Vec<u8>, // pushed but not yet consumed, starting from the partially consumed byte
+    bit: usize,     // # bits consumed within input
+    offset: u64,    // # bytes discarded from the front of input so far
+    is_finished: bool, // no more input is to be pushed
+    member_idx: usize,
+    total_out: u64,                  // # bytes produced so far
+    member_out: u64,                 // # bytes produced so far within the current member
+    num_blocks: u64,                 // # blocks begun within the current member
+    block: Option<(u64, BlockType)>, // index and type of the block being read, if any
+    window: SlidingWindow,
+    pending: Range<usize>, // inflated into the write buffer but not yet pulled
+    ll_decoder: HuffmanDecoder,
+    dist_decoder: HuffmanDecoder,
+    verifier: Verifier,
+}
+
+impl PushDecoder {
+    pub fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    /// `recover` is not supported, whereas `salvage` only changes the error for truncated input
+    pub fn with_options(options: Options) -> Self {
+        Self {
+            verifier: Verifier::new(options.format),
+            options,
+            state: State::Header,
+            input: Vec::new(),
+            bit: 0,
+            offset: 0,
+            is_finished: false,
+            member_idx: 0,
+            total_out: 0,
+            member_out: 0,
+            num_blocks: 0,
+            block: None,
+            window: SlidingWindow::new(),
+            pending: 0..0,
+            ll_decoder: HuffmanDecoder::uninitialized(),
+            dist_decoder: HuffmanDecoder::uninitialized(),
+        }
+    }
+
+    /// feed the next slice of the compressed input
+    pub fn push(&mut self, xs: &[u8]) {
+        self.input.drain(..self.bit / 8);
+        self.offset += (self.bit / 8) as u64;
+        self.bit %= 8;
+        self.input.extend_from_slice(xs);
+    }
+
+    /// let the decoder know that no more input is to be pushed,
+    /// so that an incomplete input is reported as an error rather than `Status::NeedInput`
+    pub fn finish(&mut self) {
+        self.is_finished = true;
+    }
+
+    /// # input bytes consumed so far, including the partially consumed byte
+    pub fn total_in(&self) -> u64 {
+        self.offset + self.bit.div_ceil(8) as u64
+    }
+
+    /// the input pushed but not consumed, e.g., what follows the stream in a container,
+    /// once `Status::End` is reached
+    /// the partially consumed byte, if any, counts as consumed
+    pub fn into_inner(mut self) -> Vec<u8> {
+        self.input
+            .drain(..self.bit.div_ceil(8).min(self.input.len()));
+        self.input
+    }
+
+    /// decode as much as possible into out, up to the next header or the end of the stream
+    /// `Status::Data(0)` is returned only if out is empty
+    pub fn pull(&mut self, out: &mut [u8]) -> Result<Status> {
+        loop {
+            match self.step(out) {
+                Ok(Some(status)) => return Ok(status),
+                Ok(None) => {}
+                Err(e) => return Err(e.with_context(self.context())),
+            }
+        }
+    }
+
+    /// where the decoder is at
+    pub fn context(&self) -> Context {
+        Context {
+            member: self.member_idx,
+            bit_position: Some(self.offset * 8 + self.bit as u64),
+            output_position: self.total_out,
+            block: self.block,
+        }
+    }
+
+    /// run f on the input buffered so far, consuming what it has read on success
+    /// returns None if the input is incomplete, in which case nothing is consumed
+    fn transact<T>(
+        &mut self,
+        f: impl FnOnce(&mut SliceBitReader) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let mut reader = SliceBitReader::new(&self.input, self.bit);
+        let result = f(&mut reader);
+        if is_incomplete(&reader) {
+            self.need_input()?;
+            return Ok(None);
+        }
+        let value = result?;
+        self.bit = reader.bit_position();
+        Ok(Some(value))
+    }
+
+    /// `Status::NeedInput`, or an error if no more input is to come
+    fn need_input(&self) -> Result<Status> {
+        match self.is_finished {
+            false => Ok(Status::NeedInput),
+            true if self.options.salvage => Err(Error::Truncated {
+                compressed_offset: self.offset + self.input.len() as u64,
+                output_len: self.total_out,
+            }),
+            true => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    /// returns None to continue onto the next state
+    fn step(&mut self, out: &mut [u8]) -> Result<Option<Status>> {
+        if out.is_empty() {
+            return Ok(Some(Status::Data(0)));
+        }
+        if !self.pending.is_empty() {
+            let n = out.len().min(self.pending.len());
+            let begin = self.pending.start;
+            out[..n].copy_from_slice(&self.window.write_buffer()[begin..begin + n]);
+            self.verifier.update(&out[..n]);
+            self.pending.start += n;
+            if self.pending.is_empty() {
+                self.window.slide(self.pending.end);
+                self.pending = 0..0;
+            }
+            return Ok(Some(Status::Data(n)));
+        }
+        let status = match self.state {
+            State::Header => {
+                if self.bit == self.input.len() * 8 {
+                    if !self.is_finished {
+                        return Ok(Some(Status::NeedInput));
+                    } else if self.member_idx == 0 {
+                        return Err(Error::EmptyInput);
+                    }
+                    self.state = State::Done;
+                    return Ok(Some(Status::End(self.total_in())));
+                }
+                if self.member_idx > 0 && self.options.format == Format::Gzip {
+                    let rest = &self.input[self.bit / 8..];
+                    if rest.len() < GZIP_MAGIC_LEN && !self.is_finished {
+                        // too short to tell whether another member follows
+                        return Ok(Some(Status::NeedInput));
+                    }
+                    let rest = &rest[..rest.len().min(GZIP_MAGIC_LEN)];
+                    if check_trailing(rest, self.options.trailing)?.is_some() {
+                        self.state = State::Trailing(true, 0);
+                        return Ok(None);
+                    }
+                }
+                self.options
+                    .limits
+                    .check_members(self.member_idx as u64 + 1)?;
+                let header = match self.options.format {
+                    Format::Gzip => {
+                        let options = self.options.header.clone();
+                        match self.transact(|reader| Header::read_with_options(reader, &options))? {
+                            Some(header) => Some(header),
+                            None => return Ok(Some(Status::NeedInput)),
+                        }
+                    }
+                    Format::Zlib => {
+                        let Some(header) = self.transact(|reader| ZlibHeader::read(reader))? else {
+                            return Ok(Some(Status::NeedInput));
+                        };
+                        if let Some(dictid) = header.dictid {
+                            set_dictionary(&mut self.window, &self.options, dictid)?;
+                        }
+                        None
+                    }
+                    Format::Raw => None,
+                };
+                self.state = State::Block;
+                self.member_idx += 1;
+                self.member_out = 0;
+                self.num_blocks = 0;
+                header.map(Status::Header)
+            }
+            State::Block => {
+                let Some((is_final, block)) = self.transact(read_block_header)? else {
+                    return Ok(Some(Status::NeedInput));
+                };
+                let block_type = match block {
+                    Block::Stored(_) => BlockType::Stored,
+                    Block::Fixed => BlockType::Fixed,
+                    Block::Dynamic(..) => BlockType::Dynamic,
+                };
+                self.block = Some((self.num_blocks, block_type));
+                self.num_blocks += 1;
+                self.state = match block {
+                    Block::Stored(len) => State::Stored(len, is_final),
+                    Block::Fixed => {
+                        self.ll_decoder = HuffmanDecoder::new(CodeBook::default_ll());
+                        self.dist_decoder = HuffmanDecoder::new(CodeBook::default_dist());
+                        State::Inflate(is_final)
+                    }
+                    Block::Dynamic(ll_decoder, dist_decoder) => {
+                        self.ll_decoder = ll_decoder;
+                        self.dist_decoder = dist_decoder;
+                        State::Inflate(is_final)
+                    }
+                };
+                None
+            }
+            State::Stored(len, is_final) => {
+                let begin = self.bit / 8; // byte-aligned
+                let n = len.min(self.input.len() - begin).min(out.len());
+                if n == 0 && len > 0 {
+                    return self.need_input().map(Some);
+                }
+                out[..n].copy_from_slice(&self.input[begin..begin + n]);
+                self.bit += n * 8;
+                self.produced(n)?;
+                self.verifier.update(&out[..n]);
+                self.window.extend_history(&out[..n]);
+                if len == n {
+                    self.block = None;
+                }
+                self.state = match len - n {
+                    0 if is_final => State::Footer,
+                    0 => State::Block,
+                    left => State::Stored(left, is_final),
+                };
+                (n > 0).then_some(Status::Data(n))
+            }
+            State::Inflate(is_final) => {
+                // inflate no more than the limits allow before checking them
+                let allowance = self
+                    .options
+                    .limits
+                    .allowance(self.total_out, self.member_out);
+                let direct_len = out.len().min(allowance);
+                let is_direct = direct_len >= MIN_DIRECT_BUFFER;
+                let (n, stop) = if is_direct {
+                    let (n, stop) = inflate_symbols(
+                        &mut out[..direct_len],
+                        self.window.history(),
+                        &self.input,
+                        &mut self.bit,
+                        &self.ll_decoder,
+                        &self.dist_decoder,
+                    )?;
+                    self.produced(n)?;
+                    self.verifier.update(&out[..n]);
+                    self.window.extend_history(&out[..n]);
+                    (n, stop)
+                } else {
+                    let boundary = self.window.boundary();
+                    let (history, buf) = self.window.buffer().split_at_mut(boundary);
+                    let (n, stop) = inflate_symbols(
+                        buf,
+                        history,
+                        &self.input,
+                        &mut self.bit,
+                        &self.ll_decoder,
+                        &self.dist_decoder,
+                    )?;
+                    self.produced(n)?;
+                    self.pending = 0..n;
+                    (n, stop)
+                };
+                if let Stop::EndOfBlock = stop {
+                    self.block = None;
+                }
+                match stop {
+                    Stop::EndOfBlock if is_final => self.state = State::Footer,
+                    Stop::EndOfBlock => self.state = State::Block,
+                    Stop::Full => {}
+                    Stop::NeedInput if n == 0 => {
+                        return self.need_input().map(Some);
+                    }
+                    Stop::NeedInput => {}
+                }
+                match n {
+                    0 => None,
+                    _ if is_direct => Some(Status::Data(n)),
+                    _ => return self.step(out), // from pending
+                }
+            }
+            State::Footer => match self.options.format {
+                Format::Gzip => {
+                    let Some(footer) = self.transact(|reader| Footer::read(reader))? else {
+                        return Ok(Some(Status::NeedInput));
+                    };
+                    self.verifier.verify(&Produce::Footer(footer))?;
+                    self.state = if self.options.single_member {
+                        State::End
+                    } else {
+                        State::Header
+                    };
+                    self.window = SlidingWindow::new(); // reset history
+                    None
+                }
+                Format::Zlib => {
+                    let Some(footer) = self.transact(|reader| ZlibFooter::read(reader))? else {
+                        return Ok(Some(Status::NeedInput));
+                    };
+                    self.verifier.verify(&Produce::ZlibFooter(footer))?;
+                    self.state = State::End; // zlib stream has a single member
+                    None
+                }
+                Format::Raw => {
+                    self.state = State::End;
+                    None
+                }
+            },
+            State::Trailing(is_zero, len) => {
+                // ignore the rest of the input
+                let rest = &self.input[self.bit / 8..];
+                let is_zero = is_zero && rest.iter().all(|x| *x == 0);
+                if !is_zero && self.options.trailing == TrailingPolicy::IgnoreZeros {
+                    return Err(Error::InvalidGzHeader);
+                }
+                let len = len + rest.len() as u64;
+                self.bit = self.input.len() * 8;
+                if !self.is_finished {
+                    self.state = State::Trailing(is_zero, len);
+                    return Ok(Some(Status::NeedInput));
+                }
+                self.state = State::End;
+                let policy = applied_policy(self.options.trailing, is_zero);
+                Some(Status::Trailing(policy, len))
+            }
+            State::End => {
+                self.state = State::Done;
+                Some(Status::End(self.total_in()))
+            }
+            State::Done => Some(Status::End(self.total_in())),
+        };
+        Ok(status)
+    }
+
+    /// account for n bytes produced, checking them against the limits
+    fn produced(&mut self, n: usize) -> Result<()> {
+        self.total_out += n as u64;
+        self.member_out += n as u64;
+        let total_in = self.total_in();
+        let limits = &self.options.limits;
+        limits.check_output(self.total_out, self.member_out, total_in)
+    }
+}
+
+impl Default for PushDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Block {
+    Stored(usize), // LEN
+    Fixed,
+    Dynamic(HuffmanDecoder, HuffmanDecoder),
+}
+
+/// read the header of the next block, and LEN or the codebooks depending on the type
+fn read_block_header(reader: &mut SliceBitReader) -> Result<(bool, Block)> {
+    let header = reader.read_bits(3)?;
+    let is_final = header & 1 == 1;
+    let block = match header & 0b110 {
+        0b000 => {
+            reader.byte_align();
+            let len = reader.read_bits(16)?;
+            let nlen = reader.read_bits(16)?;
+            if len ^ nlen != 0xFFFF {
+                return Err(Error::BlockType0LenMismatch);
+            }
+            Block::Stored(len as usize)
+        }
+        0b010 => Block::Fixed,
+        0b100 => {
+            let (ll_decoder, dist_decoder) = read_dynamic_codebooks(reader)?;
+            Block::Dynamic(ll_decoder, dist_decoder)
+        }
+        _ => return Err(Error::InvalidBlockType),
+    };
+    Ok((is_final, block))
+}
+
+/// whether the reader has run past the end of the input, i.e., the unit, or the error,
+/// depends on the zero-padded bits rather than the input itself
+fn is_incomplete(reader: &SliceBitReader) -> bool {
+    reader.is_overrun()
+}
+
+/// inflate the symbols into out, preceded by the history, until the end of the block,
+/// out is full, or the input runs out, in which case the incomplete symbol is left unconsumed
+/// returns # bytes written
+fn inflate_symbols(
+    out: &mut [u8],
+    history: &[u8],
+    input: &[u8],
+    bit: &mut usize,
+    ll_decoder: &HuffmanDecoder,
+    dist_decoder: &HuffmanDecoder,
+) -> Result<(usize, Stop)> {
+    let mut reader = SliceBitReader::new(input, *bit);
+    let result = decode_into_exact(out, history, &mut reader, ll_decoder, dist_decoder);
+    *bit = reader.bit_position();
+    match result {
+        Ok(DecodeResult::Done(n)) => Ok((n, Stop::EndOfBlock)),
+        Ok(DecodeResult::WindowIsFull(n)) => Ok((n, Stop::Full)),
+        Ok(DecodeResult::Error(_, n)) if is_incomplete(&reader) => {
+            *bit = reader.marked_position();
+            Ok((n, Stop::NeedInput))
+        }
+        Ok(DecodeResult::Error(e, _)) | Err(e) => {
+            *bit = reader.marked_position();
+            Err(e)
+        }
+    }
+}
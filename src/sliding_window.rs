@@ -41,4 +41,22 @@ impl SlidingWindow {
     pub fn boundary(&self) -> usize {
         self.cur
     }
+
+    /// up to 32kB of the most recent history
+    pub fn history(&self) -> &[u8] {
+        &self.data[self.cur.saturating_sub(MAX_DISTANCE as usize)..self.cur]
+    }
+
+    /// append data to the history, keeping at most 32kB of it
+    pub fn extend_history(&mut self, xs: &[u8]) {
+        let n = xs.len().min(MAX_DISTANCE as usize);
+        self.write_buffer()[..n].copy_from_slice(&xs[xs.len() - n..]);
+        self.slide(n);
+    }
+}
+
+impl Default for SlidingWindow {
+    fn default() -> Self {
+        Self::new()
+    }
 }
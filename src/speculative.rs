@@ -0,0 +1,585 @@
+//! Speculative parallel decompression of gzip members, in the spirit of pugz and rapidgzip
+//!
+//! The compressed input is split into chunks, each of which is inflated on its own thread
+//! starting from the first deflate block found within it.
+//! As the 32kB of history preceding a chunk is not known yet at that point,
+//! back-references into it are decoded as placeholders,
+//! which are resolved once the preceding chunk has been resolved.
+//! A chunk is accepted only if the preceding chunk ends exactly where it begins;
+//! otherwise inflating resumes from the end of the preceding chunk in the next round.
+
+use crate::bitread::{BitRead, BitReader};
+use crate::codebook::{CodeBook, CODE_LENGTH_ORDER, MAX_CODELENGTH};
+use crate::error::{Context, Error, Result};
+use crate::footer::Footer;
+use crate::header::{Header, HeaderOptions};
+use crate::huffman_decoder::HuffmanDecoder;
+use crate::lz77::{read_next_code, Code, MAX_DISTANCE};
+use crate::producer::{
+    check_trailing, read_dynamic_codebooks, Limits, Options, Produce, TrailingPolicy,
+};
+use crc32fast::Hasher;
+use std::collections::VecDeque;
+use std::io::Read;
+
+/// # compressed bytes per chunk
+const CHUNK_SIZE: u64 = 1 << 20;
+/// a chunk ends at the first block boundary once it has produced this many bytes
+/// this bounds the memory usage for highly compressed data
+const MAX_CHUNK_OUTPUT: usize = 32 << 20;
+const WINDOW_SIZE: usize = MAX_DISTANCE as usize;
+
+enum State {
+    Header,
+    Blocks,
+    Footer,
+    End,
+    Done,
+}
+
+struct Chunk {
+    start: u64,     // bit offset of the first block
+    end: u64,       // bit offset past the last block
+    is_final: bool, // whether the last block is the final block of the member
+    prefix_len: usize,
+    /// history followed by the inflated data
+    /// bytes are stored as they are, and 256 + k stands for the k-th byte of the unknown 32kB window
+    data: Vec<u16>,
+    exceeded: Option<Error>, // the limits were exceeded, and the chunk is cut short there
+}
+
+impl Chunk {
+    fn inflated(&self) -> &[u16] {
+        &self.data[self.prefix_len..]
+    }
+}
+
+/// The output produced before a round, against which each chunk checks the limits as it inflates
+/// The output of the chunks preceding it is not known yet and counted as none,
+/// so a chunk fails the check only if it would fail with the preceding chunks accounted for
+#[derive(Clone, Copy)]
+struct Budget {
+    limits: Limits,
+    total_out: u64,
+    member_out: u64,
+}
+
+impl Budget {
+    /// check the limits given # bytes inflated by the chunk and # input bytes consumed so far
+    fn check(&self, n: usize, total_in: u64) -> Result<()> {
+        let n = n as u64;
+        self.limits
+            .check_output(self.total_out + n, self.member_out + n, total_in)
+    }
+
+    /// # bytes the chunk may inflate at once, given # bytes inflated so far
+    fn allowance(&self, n: usize) -> usize {
+        let n = n as u64;
+        self.limits
+            .allowance(self.total_out + n, self.member_out + n)
+    }
+}
+
+/// Inflates gzip members with up to num_threads threads
+/// The whole compressed input is read into memory first, whereas the output is produced
+/// in rounds of up to num_threads chunks
+pub struct SpeculativeProducer<R: Read> {
+    read: Option<R>, // taken once the input is loaded
+    input: Vec<u8>,
+    num_threads: usize,
+    options: HeaderOptions,
+    single_member: bool,
+    trailing: TrailingPolicy,
+    limits: Limits,
+    state: State,
+    member_idx: usize,
+    bit_position: u64, // next block to inflate, or past the final block
+    window: Vec<u8>,   // up to 32kB of history preceding bit_position
+    hasher: Hasher,
+    size: u32, // # bytes of the current member, modulo 2^32
+    total_out: u64,
+    member_out: u64,
+    is_final: bool,
+    pending: VecDeque<Vec<u8>>, // inflated data yet to be produced
+}
+
+impl<R: Read> SpeculativeProducer<R> {
+    pub fn new(read: R, num_threads: usize) -> Self {
+        Self::with_options(read, num_threads, Options::default())
+    }
+
+    /// the format is always gzip, the dictionary is irrelevant, and `recover` and `salvage` are not supported
+    pub fn with_options(read: R, num_threads: usize, options: Options) -> Self {
+        Self {
+            read: Some(read),
+            input: Vec::new(),
+            num_threads: num_threads.max(1),
+            options: options.header,
+            single_member: options.single_member,
+            trailing: options.trailing,
+            limits: options.limits,
+            state: State::Header,
+            member_idx: 0,
+            bit_position: 0,
+            window: Vec::new(),
+            hasher: Hasher::new(),
+            size: 0,
+            total_out: 0,
+            member_out: 0,
+            is_final: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// # input bytes consumed so far, including the partially consumed byte
+    fn byte_position(&self) -> usize {
+        self.bit_position.div_ceil(8) as usize
+    }
+
+    fn next_helper(&mut self) -> Result<Option<Produce>> {
+        let produce = match self.state {
+            State::Header => {
+                if let Some(mut read) = self.read.take() {
+                    read.read_to_end(&mut self.input)?;
+                }
+                let position = self.byte_position();
+                if position == self.input.len() {
+                    if self.member_idx == 0 {
+                        return Err(Error::EmptyInput);
+                    }
+                    self.state = State::Done;
+                    return Ok(Some(Produce::End(position as u64)));
+                }
+                if self.member_idx > 0 {
+                    if let Some(policy) = check_trailing(&self.input[position..], self.trailing)? {
+                        self.bit_position = self.input.len() as u64 * 8;
+                        self.state = State::End;
+                        let len = self.input.len() - position;
+                        return Ok(Some(Produce::Trailing(policy, len as u64)));
+                    }
+                }
+                self.limits.check_members(self.member_idx as u64 + 1)?;
+                let mut reader = BitReader::new(&self.input[position..]);
+                let header = Header::read_with_options(&mut reader, &self.options)?;
+                self.bit_position = (position as u64 + reader.position()) * 8;
+                self.member_idx += 1;
+                self.member_out = 0;
+                self.window.clear();
+                self.hasher = Hasher::new();
+                self.size = 0;
+                self.is_final = false;
+                self.state = State::Blocks;
+                Produce::Header(header)
+            }
+            State::Blocks => match self.pending.pop_front() {
+                Some(xs) => Produce::Data(xs),
+                None => {
+                    if self.is_final {
+                        self.state = State::Footer;
+                    } else {
+                        self.inflate_round()?;
+                    }
+                    return self.next_helper();
+                }
+            },
+            State::Footer => {
+                let position = self.byte_position();
+                let footer = Footer::read(&self.input[position..])?;
+                self.bit_position = (position as u64 + 8) * 8;
+                if std::mem::take(&mut self.hasher).finalize() != footer.crc32 {
+                    return Err(Error::ChecksumMismatch);
+                }
+                if self.size != footer.size {
+                    return Err(Error::SizeMismatch);
+                }
+                self.state = if self.single_member {
+                    State::End
+                } else {
+                    State::Header
+                };
+                Produce::Footer(footer)
+            }
+            State::End => {
+                self.state = State::Done;
+                Produce::End(self.byte_position() as u64)
+            }
+            State::Done => return Ok(None),
+        };
+        Ok(Some(produce))
+    }
+
+    /// inflate up to num_threads chunks from the current block boundary
+    fn inflate_round(&mut self) -> Result<()> {
+        let input = &self.input[..];
+        let end = input.len() as u64 * 8;
+        let start = self.bit_position;
+        // chunk k begins at the first block at or after bounds[k]
+        // and ends at the first block boundary at or after bounds[k + 1]
+        let bounds = (0..=self.num_threads as u64)
+            .map(|k| (start / 8 + k * CHUNK_SIZE).saturating_mul(8).min(end))
+            .collect::<Vec<_>>();
+        let window = &self.window;
+        let budget = Budget {
+            limits: self.limits,
+            total_out: self.total_out,
+            member_out: self.member_out,
+        };
+
+        let (first, speculated) = std::thread::scope(|scope| {
+            let handles = (1..self.num_threads)
+                .filter(|k| bounds[*k] < end)
+                .map(|k| {
+                    let (from, to) = (bounds[k], bounds[k + 1]);
+                    scope.spawn(move || speculate(input, from, to, budget))
+                })
+                .collect::<Vec<_>>();
+            let prefix = window.iter().map(|x| *x as u16).collect();
+            let first = inflate_chunk(input, start, bounds[1], prefix, budget);
+            let speculated = handles
+                .into_iter()
+                .map(|handle| handle.join().ok().flatten())
+                .collect::<Vec<_>>();
+            (first, speculated)
+        });
+
+        // keep the chunks as long as they are contiguous
+        let mut chunks = vec![first?];
+        for chunk in speculated {
+            let last = chunks.last().unwrap();
+            match chunk {
+                Some(chunk)
+                    if !last.is_final
+                        && last.exceeded.is_none()
+                        && is_same_block(input, last.end, chunk.start) =>
+                {
+                    chunks.push(chunk)
+                }
+                _ => break,
+            }
+        }
+        if let Some(e) = chunks.last_mut().unwrap().exceeded.take() {
+            return Err(e);
+        }
+
+        // the window preceding each chunk is resolved one after another
+        let mut windows = Vec::with_capacity(chunks.len());
+        let mut window = std::mem::take(&mut self.window);
+        for chunk in &chunks {
+            let inflated = chunk.inflated();
+            let tail = resolve(
+                &inflated[inflated.len().saturating_sub(WINDOW_SIZE)..],
+                &window,
+            )?;
+            let mut next = window[window.len().saturating_sub(WINDOW_SIZE - tail.len())..].to_vec();
+            next.extend_from_slice(&tail);
+            windows.push(std::mem::replace(&mut window, next));
+        }
+
+        // while the rest of the chunks are resolved in parallel
+        let resolved = std::thread::scope(|scope| {
+            let handles = chunks
+                .iter()
+                .zip(&windows)
+                .map(|(chunk, window)| {
+                    scope.spawn(move || {
+                        let xs = resolve(chunk.inflated(), window)?;
+                        let mut hasher = Hasher::new();
+                        hasher.update(&xs);
+                        Ok((xs, hasher))
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle.join().unwrap_or_else(|_| {
+                        Err(std::io::Error::other("worker thread panicked").into())
+                    })
+                })
+                .collect::<Vec<Result<_>>>()
+        });
+        for result in resolved {
+            let (xs, hasher) = result?;
+            self.hasher.combine(&hasher);
+            self.size = self.size.wrapping_add(xs.len() as u32);
+            self.total_out += xs.len() as u64;
+            self.member_out += xs.len() as u64;
+            if !xs.is_empty() {
+                self.pending.push_back(xs);
+            }
+        }
+
+        let last = chunks.last().unwrap();
+        let total_in = last.end.div_ceil(8);
+        self.limits
+            .check_output(self.total_out, self.member_out, total_in)?;
+        self.bit_position = last.end;
+        self.is_final = last.is_final;
+        self.window = window;
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for SpeculativeProducer<R> {
+    type Item = Produce;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_helper().unwrap_or_else(|e| {
+            self.state = State::Done;
+            let context = Context {
+                member: self.member_idx,
+                bit_position: Some(self.bit_position),
+                output_position: self.total_out,
+                block: None,
+            };
+            Some(Produce::Err(e.with_context(context)))
+        })
+    }
+}
+
+/// inflate from the first block within [from, to) that can be inflated,
+/// up to the first block boundary at or after to
+fn speculate(input: &[u8], from: u64, to: u64, budget: Budget) -> Option<Chunk> {
+    let placeholders = (0..WINDOW_SIZE as u16).map(|k| 256 + k).collect::<Vec<_>>();
+    (from..to)
+        .filter(|bit| is_block_candidate(input, *bit))
+        .find_map(|bit| inflate_chunk(input, bit, to, placeholders.clone(), budget).ok())
+}
+
+/// inflate the blocks from start up to the first block boundary at or after stop,
+/// or up to the final block, or up to where the limits are exceeded
+/// prefix is the history preceding start, possibly with placeholders
+fn inflate_chunk(
+    input: &[u8],
+    start: u64,
+    stop: u64,
+    prefix: Vec<u16>,
+    budget: Budget,
+) -> Result<Chunk> {
+    let offset = start / 8 * 8;
+    let mut reader = BitReader::new(&input[(start / 8) as usize..]);
+    let nbits = (start % 8) as u32;
+    if nbits > 0 {
+        reader.read_bits(nbits)?;
+    }
+    let prefix_len = prefix.len();
+    let mut data = prefix;
+    let mut max_len = prefix_len.saturating_add(budget.allowance(0));
+    let chunk = |end, is_final, data, exceeded| Chunk {
+        start,
+        end,
+        is_final,
+        prefix_len,
+        data,
+        exceeded,
+    };
+    loop {
+        let position = offset + reader.bit_position();
+        let is_full = data.len() - prefix_len >= MAX_CHUNK_OUTPUT;
+        if position > start && (position >= stop || is_full) {
+            return Ok(chunk(position, false, data, None));
+        }
+
+        let header = reader.read_bits(3)?;
+        let decoders = match header & 0b110 {
+            0b000 => {
+                reader.byte_align();
+                let len = reader.read_bits(16)?;
+                let nlen = reader.read_bits(16)?;
+                if len ^ nlen != 0xFFFF {
+                    return Err(Error::BlockType0LenMismatch);
+                }
+                let mut buf = vec![0; len as usize];
+                reader.read_exact(&mut buf)?;
+                data.extend(buf.into_iter().map(u16::from));
+                None
+            }
+            0b010 => Some((
+                HuffmanDecoder::new(CodeBook::default_ll()),
+                HuffmanDecoder::new(CodeBook::default_dist()),
+            )),
+            0b100 => Some(read_dynamic_codebooks(&mut reader)?),
+            _ => return Err(Error::InvalidBlockType),
+        };
+        loop {
+            if data.len() >= max_len {
+                // check the limits before inflating any further
+                let n = data.len() - prefix_len;
+                let total_in = (offset + reader.bit_position()).div_ceil(8);
+                if let Err(e) = budget.check(n, total_in) {
+                    let position = offset + reader.bit_position();
+                    return Ok(chunk(position, false, data, Some(e)));
+                }
+                max_len = data.len().saturating_add(budget.allowance(n));
+            }
+            let Some((ll_decoder, dist_decoder)) = &decoders else {
+                break;
+            };
+            if inflate_block(&mut reader, &mut data, ll_decoder, dist_decoder, max_len)? {
+                break;
+            }
+        }
+        if reader.is_overrun() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+
+        if header & 1 == 1 {
+            let position = offset + reader.bit_position();
+            return Ok(chunk(position, true, data, None));
+        }
+    }
+}
+
+/// inflate the rest of the block, pausing once data grows to max_len
+/// returns whether the end of the block is reached
+fn inflate_block<B: BitRead>(
+    reader: &mut B,
+    data: &mut Vec<u16>,
+    ll_decoder: &HuffmanDecoder,
+    dist_decoder: &HuffmanDecoder,
+    max_len: usize,
+) -> Result<bool> {
+    while data.len() < max_len {
+        match read_next_code(reader, ll_decoder, dist_decoder)? {
+            Code::Literal(x) => data.push(x as u16),
+            Code::Dictionary { distance, length } => {
+                let distance = distance as usize;
+                let length = length as usize;
+                if distance > data.len() {
+                    return Err(Error::DistanceTooMuch);
+                }
+                let begin = data.len() - distance;
+                if distance >= length {
+                    data.extend_from_within(begin..begin + length);
+                } else {
+                    for idx in begin..begin + length {
+                        data.push(data[idx]);
+                    }
+                }
+            }
+            Code::EndOfBlock => return Ok(true),
+        }
+    }
+    Ok(false)
+}
+
+/// replace the placeholders with the bytes from the window
+fn resolve(xs: &[u16], window: &[u8]) -> Result<Vec<u8>> {
+    // placeholders index into a full 32kB window,
+    // whereas the window is shorter near the beginning of the member
+    let missing = (WINDOW_SIZE - window.len()) as u16;
+    if missing > 0 && xs.iter().any(|x| (256..256 + missing).contains(x)) {
+        return Err(Error::DistanceTooMuch);
+    }
+    let mut lookup = (0..=255).collect::<Vec<u8>>();
+    lookup.resize(256 + missing as usize, 0);
+    lookup.extend_from_slice(window);
+    Ok(xs.iter().map(|x| lookup[*x as usize]).collect())
+}
+
+/// up to 57 bits at the bit offset, zero-padded past the end of the input
+fn peek(input: &[u8], bit: u64, n: u32) -> u64 {
+    let begin = ((bit / 8) as usize).min(input.len());
+    let mut bytes = [0u8; 8];
+    let len = (input.len() - begin).min(bytes.len());
+    bytes[..len].copy_from_slice(&input[begin..begin + len]);
+    (u64::from_le_bytes(bytes) >> (bit % 8)) & ((1 << n) - 1)
+}
+
+/// whether a non-final dynamic or stored block plausibly begins at the bit offset
+fn is_block_candidate(input: &[u8], bit: u64) -> bool {
+    peek(input, bit, 1) == 0 && is_block_header(input, bit)
+}
+
+/// whether a dynamic or stored block, final or not, plausibly begins at the bit offset
+pub(crate) fn is_block_header(input: &[u8], bit: u64) -> bool {
+    match peek(input, bit + 1, 2) {
+        0b10 => is_dynamic_header(input, bit + 3),
+        // the block header is followed by zero padding up to the byte boundary, as with zlib,
+        // so we look only at the last 3 bits of each byte
+        0b00 if bit % 8 == 5 => is_stored_header(input, (bit / 8 + 1) as usize),
+        _ => false,
+    }
+}
+
+/// whether a dynamic block header with complete huffman codes begins at the bit offset,
+/// just past BFINAL and BTYPE
+fn is_dynamic_header(input: &[u8], mut bit: u64) -> bool {
+    let hlit = peek(input, bit, 5) as usize + 257;
+    let hdist = peek(input, bit + 5, 5) as usize + 1;
+    let hclen = peek(input, bit + 10, 4) as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return false;
+    }
+    bit += 14;
+
+    let mut cl_lengths = [0; 19];
+    let bits = peek(input, bit, 3 * hclen as u32);
+    for (k, idx) in CODE_LENGTH_ORDER.into_iter().take(hclen).enumerate() {
+        cl_lengths[idx] = (bits >> (3 * k)) as u32 & 0b111;
+    }
+    bit += 3 * hclen as u64;
+    if !is_complete(&cl_lengths) {
+        return false;
+    }
+
+    let Ok(cl_codes) = CodeBook::new(&cl_lengths) else {
+        return false;
+    };
+    let cl_decoder = HuffmanDecoder::new(cl_codes);
+    let mut lengths = [0; 316];
+    let mut n = 0;
+    while n < hlit + hdist {
+        let Ok((cl_code, len)) = cl_decoder.decode(peek(input, bit, 7) as u32) else {
+            return false;
+        };
+        bit += len as u64;
+        let (length, repeat, nbits) = match cl_code {
+            0..=15 => (cl_code, 1, 0),
+            16 if n > 0 => (lengths[n - 1], 3, 2),
+            17 => (0, 3, 3),
+            18 => (0, 11, 7),
+            _ => return false,
+        };
+        let repeat = repeat + peek(input, bit, nbits) as usize;
+        bit += nbits as u64;
+        if n + repeat > hlit + hdist {
+            return false;
+        }
+        lengths[n..n + repeat].fill(length);
+        n += repeat;
+    }
+
+    let (ll_lengths, dist_lengths) = lengths[..n].split_at(hlit);
+    ll_lengths[256] > 0
+        && is_complete(ll_lengths)
+        && (is_complete(dist_lengths) || dist_lengths.iter().all(|l| *l == 0))
+}
+
+/// whether a stored block plausibly begins at the byte offset, just past the padding
+fn is_stored_header(input: &[u8], begin: usize) -> bool {
+    let Some(bytes) = input.get(begin..begin + 4) else {
+        return false;
+    };
+    let len = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let nlen = u16::from_le_bytes([bytes[2], bytes[3]]);
+    len ^ nlen == 0xFFFF && begin + 4 + len as usize <= input.len()
+}
+
+/// whether the code lengths form a complete prefix code, or a single code of length 1
+fn is_complete(lengths: &[u32]) -> bool {
+    let kraft = lengths
+        .iter()
+        .filter(|l| **l > 0)
+        .map(|l| 1 << (MAX_CODELENGTH - l))
+        .sum::<u32>();
+    let count = lengths.iter().filter(|l| **l > 0).count();
+    kraft == 1 << MAX_CODELENGTH || (kraft == 1 << (MAX_CODELENGTH - 1) && count == 1)
+}
+
+/// whether inflating from either bit offset is the same,
+/// i.e., they are the same or both are stored block headers followed by the same data
+fn is_same_block(input: &[u8], a: u64, b: u64) -> bool {
+    let stored_data = |bit: u64| (peek(input, bit, 3) == 0).then(|| (bit + 3).div_ceil(8));
+    a == b || (stored_data(a).is_some() && stored_data(a) == stored_data(b))
+}
@@ -0,0 +1,57 @@
+use crate::error::{Error, Result};
+use std::io::Read;
+
+const DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+const FDICT: u8 = 0x20;
+
+pub struct ZlibHeader {
+    pub cmf: u8,
+    pub flg: u8,
+    pub dictid: Option<u32>, // Adler-32 of the preset dictionary
+}
+
+impl ZlibHeader {
+    pub fn read(mut read: impl Read) -> Result<Self> {
+        let mut buf = [0u8; 2];
+        read.read_exact(&mut buf)?;
+        let [cmf, flg] = buf;
+        if cmf & 0x0F != DEFLATE || cmf >> 4 > MAX_CINFO || u16::from_be_bytes(buf) % 31 != 0 {
+            return Err(Error::InvalidZlibHeader);
+        }
+
+        let dictid = if flg & FDICT != 0 {
+            let mut buf = [0u8; 4];
+            read.read_exact(&mut buf)?;
+            Some(u32::from_be_bytes(buf))
+        } else {
+            None
+        };
+
+        Ok(Self { cmf, flg, dictid })
+    }
+
+    /// LZ77 window size used by the compressor
+    pub fn window_size(&self) -> usize {
+        1 << ((self.cmf >> 4) + 8)
+    }
+
+    /// compression level, from 0 (fastest) to 3 (maximum compression)
+    pub fn level(&self) -> u8 {
+        self.flg >> 6
+    }
+}
+
+pub struct ZlibFooter {
+    pub adler32: u32,
+}
+
+impl ZlibFooter {
+    pub fn read(mut read: impl Read) -> Result<Self> {
+        let mut buf = [0u8; 4];
+        read.read_exact(&mut buf)?;
+        Ok(Self {
+            adler32: u32::from_be_bytes(buf),
+        })
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{compress, text};
+use gunzip::bgzf::{BgzfReader, ParallelBgzfReader};
+use gunzip::error::{Context, Error};
+use std::io::{Cursor, Read};
+
+const BLOCK_LEN: usize = 20_000;
+
+/// a BGZF block of the data, with garbage bytes between the member and the end of the block
+fn block(data: &[u8], garbage: &[u8]) -> Vec<u8> {
+    let gz = compress(data, 6);
+    let block_size = gz.len() + 8 + garbage.len();
+    let mut block = gz[..10].to_vec();
+    block[3] |= 4; // FEXTRA
+    block.extend(6u16.to_le_bytes());
+    block.extend(b"BC");
+    block.extend(2u16.to_le_bytes());
+    block.extend((block_size as u16 - 1).to_le_bytes());
+    block.extend(&gz[10..]);
+    block.extend(garbage);
+    block
+}
+
+/// BGZF of the data, along with the compressed offset of each block, ending with an empty block
+fn bgzf(data: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    let mut bgzf = Vec::new();
+    let mut offsets = Vec::new();
+    for chunk in data.chunks(BLOCK_LEN).chain([&[][..]]) {
+        offsets.push(bgzf.len());
+        bgzf.extend(block(chunk, &[]));
+    }
+    (bgzf, offsets)
+}
+
+fn read_all(bgzf: &[u8], num_threads: Option<usize>) -> std::io::Result<Vec<u8>> {
+    let read = Cursor::new(bgzf.to_vec());
+    let mut out = Vec::new();
+    match num_threads {
+        None => BgzfReader::new(read).read_to_end(&mut out)?,
+        Some(n) => ParallelBgzfReader::new(read, n).read_to_end(&mut out)?,
+    };
+    Ok(out)
+}
+
+fn error_of(e: std::io::Error) -> (Error, Context) {
+    let e = *e.into_inner().unwrap().downcast::<Error>().unwrap();
+    let context = e.context().cloned().unwrap();
+    (e, context)
+}
+
+#[test]
+fn reads_blocks() {
+    let data = text(300_000, 1);
+    let (bgzf, _) = bgzf(&data);
+    for num_threads in [None, Some(1), Some(4)] {
+        assert!(read_all(&bgzf, num_threads).unwrap() == data);
+    }
+}
+
+#[test]
+fn seeks_to_virtual_offsets() {
+    let data = text(300_000, 2);
+    let (bgzf, offsets) = bgzf(&data);
+    let mut reader = BgzfReader::new(Cursor::new(bgzf));
+    for (idx, pos) in [(7, 123), (2, 0), (7, 19_999), (0, 5)] {
+        reader
+            .seek_virtual((offsets[idx] as u64) << 16 | pos)
+            .unwrap();
+        assert_eq!(reader.virtual_offset(), (offsets[idx] as u64) << 16 | pos);
+        let mut buf = [0; 10];
+        reader.read_exact(&mut buf).unwrap();
+        let begin = idx * BLOCK_LEN + pos as usize;
+        assert_eq!(buf, data[begin..begin + 10]);
+    }
+}
+
+#[test]
+fn errors_point_at_the_block() {
+    let data = text(300_000, 3);
+    let (mut bgzf, offsets) = bgzf(&data);
+    let k = 5;
+    bgzf[offsets[k + 1] - 8] ^= 1; // CRC32 of the block
+    for num_threads in [None, Some(4)] {
+        let (e, context) = error_of(read_all(&bgzf, num_threads).unwrap_err());
+        assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+        assert_eq!(context.member, k + 1);
+        assert_eq!(context.output_position, ((k + 1) * BLOCK_LEN) as u64);
+        let bit = context.bit_position.unwrap();
+        assert!((offsets[k] * 8..=offsets[k + 1] * 8).contains(&(bit as usize)));
+    }
+}
+
+#[test]
+fn invalid_block_points_at_the_garbage() {
+    let data = text(50_000, 4);
+    let mut bgzf = block(&data[..BLOCK_LEN], &[]);
+    let offset = bgzf.len();
+    let member_len = block(&data[BLOCK_LEN..], &[]).len();
+    bgzf.extend(block(&data[BLOCK_LEN..], b"garbage"));
+    for num_threads in [None, Some(4)] {
+        let (e, context) = error_of(read_all(&bgzf, num_threads).unwrap_err());
+        assert!(matches!(e.inner(), Error::InvalidBgzfBlock), "{e}");
+        assert_eq!(context.member, 2);
+        assert_eq!(context.output_position, data.len() as u64);
+        assert_eq!(context.bit_position, Some((offset + member_len) as u64 * 8));
+    }
+}
+
+#[test]
+fn truncated_block_points_at_its_beginning() {
+    let data = text(50_000, 5);
+    let (mut bgzf, offsets) = bgzf(&data);
+    bgzf.truncate(offsets[2] + 5);
+    for num_threads in [None, Some(4)] {
+        let (e, context) = error_of(read_all(&bgzf, num_threads).unwrap_err());
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof, "{e}");
+        assert_eq!(context.member, 3);
+        assert_eq!(context.output_position, 2 * BLOCK_LEN as u64);
+        assert_eq!(context.bit_position, Some(offsets[2] as u64 * 8));
+    }
+}
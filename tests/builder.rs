@@ -0,0 +1,25 @@
+mod common;
+
+use common::{compress, text, STRATEGIES};
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+#[test]
+fn strategies_agree() {
+    let data = text(3 << 20, 2);
+    let mut gz = Vec::new();
+    for part in data.chunks(1 << 20) {
+        gz.extend(compress(part, 6));
+    }
+    for strategy in STRATEGIES {
+        for threads in [1, 2, 4] {
+            let mut decompressor = Decompressor::builder()
+                .threads(threads)
+                .strategy(strategy)
+                .build(Cursor::new(gz.clone()));
+            let mut out = Vec::new();
+            decompressor.read_to_end(&mut out).unwrap();
+            assert!(out == data, "{strategy:?} with {threads} threads");
+        }
+    }
+}
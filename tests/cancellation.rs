@@ -0,0 +1,110 @@
+mod common;
+
+use common::{compress, text, STRATEGIES};
+use gunzip::{Decompressor, Strategy};
+use std::io::{Cursor, ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// what the reader does once the input has run out
+#[derive(Clone, Copy)]
+enum End {
+    Eof,
+    Fail(ErrorKind),
+    Panic,
+}
+
+/// lets it be known when dropped
+struct Reader {
+    inner: Cursor<Vec<u8>>,
+    end: End,
+    is_dropped: Arc<AtomicBool>,
+}
+
+impl Reader {
+    fn new(input: Vec<u8>, end: End) -> (Self, Arc<AtomicBool>) {
+        let is_dropped = Arc::new(AtomicBool::new(false));
+        let reader = Self {
+            inner: Cursor::new(input),
+            end,
+            is_dropped: is_dropped.clone(),
+        };
+        (reader, is_dropped)
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match (self.inner.read(buf)?, self.end) {
+            (0, End::Fail(kind)) => Err(kind.into()),
+            (0, End::Panic) => panic!("the reader has failed"),
+            (n, _) => Ok(n),
+        }
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.is_dropped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn dropped_midway() {
+    let gz = compress(&text(8 << 20, 1), 1);
+    for strategy in STRATEGIES {
+        let (read, is_dropped) = Reader::new(gz.clone(), End::Eof);
+        let mut decompressor = Decompressor::builder()
+            .threads(4)
+            .strategy(strategy)
+            .build(read);
+        let mut buf = [0; 1024];
+        decompressor.read_exact(&mut buf).unwrap();
+        let start = Instant::now();
+        drop(decompressor);
+        // the threads have been joined and have let go of the reader
+        assert!(is_dropped.load(Ordering::Relaxed), "{strategy:?}");
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < Duration::from_secs(5),
+            "{strategy:?}: {elapsed:?}"
+        );
+    }
+}
+
+#[test]
+fn reader_error_is_propagated() {
+    let data = text(1 << 20, 2);
+    let gz = compress(&data, 6);
+    let truncated = gz[..gz.len() / 2].to_vec();
+    for strategy in STRATEGIES {
+        let (read, _) = Reader::new(truncated.clone(), End::Fail(ErrorKind::ConnectionReset));
+        let mut decompressor = Decompressor::builder()
+            .threads(4)
+            .strategy(strategy)
+            .build(read);
+        let mut out = Vec::new();
+        let e = decompressor.read_to_end(&mut out).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ConnectionReset, "{strategy:?}: {e}");
+        assert!(data.starts_with(&out), "{strategy:?}");
+    }
+}
+
+#[test]
+fn reader_panic_is_reported() {
+    let gz = compress(&text(1 << 20, 3), 6);
+    let truncated = gz[..gz.len() / 2].to_vec();
+    // the threads other than the calling one read the input
+    for strategy in [Strategy::Pipelined, Strategy::SeparateChecksum] {
+        let (read, _) = Reader::new(truncated.clone(), End::Panic);
+        let mut decompressor = Decompressor::builder()
+            .threads(4)
+            .strategy(strategy)
+            .build(read);
+        let mut out = Vec::new();
+        let e = decompressor.read_to_end(&mut out).unwrap_err();
+        let msg = e.to_string();
+        assert!(msg.contains("the reader has failed"), "{strategy:?}: {msg}");
+    }
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{compress, text};
+use gunzip::{Decompressor, Strategy};
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// counts the bytes read from the inner reader
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicUsize>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[test]
+fn producer_runs_ahead_by_in_flight_chunks() {
+    let gz = compress(&text(32 << 20, 3), 0);
+    for strategy in [Strategy::Pipelined, Strategy::SeparateChecksum] {
+        let mut counts = Vec::new();
+        for in_flight in [1, 16] {
+            let count = Arc::new(AtomicUsize::new(0));
+            let read = CountingReader {
+                inner: Cursor::new(gz.clone()),
+                count: count.clone(),
+            };
+            let mut decompressor = Decompressor::builder()
+                .threads(2)
+                .strategy(strategy)
+                .in_flight(in_flight)
+                .build(read);
+            let mut buf = [0; 1024];
+            decompressor.read_exact(&mut buf).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            counts.push(count.load(Ordering::Relaxed));
+        }
+        // each stage holds up to in_flight chunks of data, along with the input buffered
+        assert!(counts[0] < 512 << 10, "{strategy:?}: {counts:?}");
+        assert!(counts[1] < 4 << 20, "{strategy:?}: {counts:?}");
+        assert!(counts[0] < counts[1], "{strategy:?}: {counts:?}");
+    }
+}
+
+#[test]
+fn multithreaded_output_matches() {
+    let data = text(3 << 20, 4);
+    let gz = compress(&data, 6);
+    for in_flight in [1, 2, 16] {
+        let mut decompressor = Decompressor::builder()
+            .threads(2)
+            .in_flight(in_flight)
+            .build(Cursor::new(gz.clone()));
+        let mut out = Vec::new();
+        decompressor.read_to_end(&mut out).unwrap();
+        assert!(out == data, "{in_flight}");
+    }
+}
+
+#[test]
+fn default_strategy_streams_for_any_thread_count() {
+    // stored blocks, so the compressed size is the decompressed size
+    let gz = compress(&text(32 << 20, 1), 0);
+    for threads in [2, 3, 8] {
+        let count = Arc::new(AtomicUsize::new(0));
+        let read = CountingReader {
+            inner: Cursor::new(gz.clone()),
+            count: count.clone(),
+        };
+        let mut decompressor = Decompressor::builder().threads(threads).build(read);
+        let mut buf = [0; 1024];
+        decompressor.read_exact(&mut buf).unwrap();
+        // give the producer time to run ahead as far as it may
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let count = count.load(Ordering::Relaxed);
+        assert!(count < 4 << 20, "{threads} threads read {count} bytes");
+    }
+}
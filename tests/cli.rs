@@ -0,0 +1,51 @@
+mod common;
+
+use common::{compress, text};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn gunzip(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gunzip"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    output
+}
+
+#[test]
+fn decompresses() {
+    let data = text(100_000, 1);
+    let output = gunzip(&["-j", "4"], &compress(&data, 6));
+    assert!(output.status.success());
+    assert!(output.stdout == data);
+}
+
+#[test]
+fn recovery_fails_on_damage() {
+    let data = text(100_000, 2);
+    let mut gz = compress(&data, 6);
+    let crc = gz.len() - 8;
+    gz[crc] ^= 1;
+
+    let output = gunzip(&[], &gz);
+    assert!(!output.status.success());
+
+    // the output is delivered, but the exit status tells that it is lossy
+    let output = gunzip(&["-r"], &gz);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout == data);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("damaged compressed bits"), "{stderr}");
+
+    let output = gunzip(&["-r"], &compress(&data, 6));
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+
+use gunzip::checksum::{Adler32Checksum, Checksum};
+use gunzip::{Decompressor, GzEncoder, Strategy};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+pub const STRATEGIES: [Strategy; 5] = [
+    Strategy::Inline,
+    Strategy::Pipelined,
+    Strategy::SeparateChecksum,
+    Strategy::Speculative,
+    Strategy::ParallelMembers,
+];
+
+/// deterministic pseudo-random bytes
+pub fn random(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+/// deterministic text of pseudo-random words, which compresses well with dynamic blocks
+pub fn text(len: usize, seed: u64) -> Vec<u8> {
+    const WORDS: [&[u8]; 16] = [
+        b"the ",
+        b"quick ",
+        b"brown ",
+        b"fox ",
+        b"jumps ",
+        b"over ",
+        b"a ",
+        b"lazy ",
+        b"dog ",
+        b"and ",
+        b"runs ",
+        b"away ",
+        b"from ",
+        b"it, ",
+        b"again.\n",
+        b"then ",
+    ];
+    let mut data = Vec::with_capacity(len + 8);
+    for x in random(len, seed) {
+        if data.len() >= len {
+            break;
+        }
+        data.extend_from_slice(WORDS[x as usize % WORDS.len()]);
+    }
+    data.truncate(len);
+    data
+}
+
+/// gzip member of the data compressed with `GzEncoder` at the level
+pub fn compress(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// raw deflate stream of the data, i.e., the gzip member without its header and footer
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let gz = compress(data, level);
+    gz[GZIP_HEADER.len()..gz.len() - 8].to_vec()
+}
+
+/// zlib stream of the data without a preset dictionary
+pub fn zlib(data: &[u8], level: u32) -> Vec<u8> {
+    let mut checksum = Adler32Checksum::new();
+    checksum.update(data);
+    let mut zlib = vec![0x78, 0x9C];
+    zlib.extend(deflate(data, level));
+    zlib.extend(checksum.checksum().to_be_bytes());
+    zlib
+}
+
+/// decompress the data single-threaded
+pub fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    Decompressor::new(std::io::Cursor::new(data.to_vec())).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// compress the data with the system gzip, None if gzip is not available
+pub fn gzip(data: &[u8], args: &[&str]) -> Option<Vec<u8>> {
+    run_gzip(data, args)
+        .filter(|(ok, _)| *ok)
+        .map(|(_, out)| out)
+}
+
+/// decompress the data with the system gzip, None if gzip is not available
+/// panics if gzip rejects the data
+pub fn gunzip(data: &[u8]) -> Option<Vec<u8>> {
+    let (ok, out) = run_gzip(data, &["-dc"])?;
+    assert!(ok, "gzip rejected the data");
+    Some(out)
+}
+
+fn run_gzip(data: &[u8], args: &[&str]) -> Option<(bool, Vec<u8>)> {
+    let mut child = Command::new("gzip")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let data = data.to_vec();
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(&data);
+    });
+    let output = child.wait_with_output().ok()?;
+    writer.join().unwrap();
+    Some((output.status.success(), output.stdout))
+}
+
+/// writes bits LSB first as deflate does
+#[derive(Default)]
+pub struct Bits {
+    pub bytes: Vec<u8>,
+    nbits: usize,
+}
+
+impl Bits {
+    pub fn push(&mut self, value: u32, len: usize) {
+        for i in 0..len {
+            if self.nbits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= ((value >> i & 1) as u8) << (self.nbits % 8);
+            self.nbits += 1;
+        }
+    }
+
+    /// huffman codes are packed starting with the MSB
+    pub fn push_code(&mut self, code: u32, len: usize) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.push(reversed, len);
+    }
+}
+
+/// a gzip member header without optional fields
+pub const GZIP_HEADER: [u8; 10] = [0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 255];
+
+/// a fixed huffman block of the ASCII literals, followed by the invalid literal/length symbol 286
+/// if damaged, and then by the end of block unless damaged and final
+pub fn fixed_block(bits: &mut Bits, literals: &[u8], is_final: bool, is_damaged: bool) {
+    bits.push(is_final as u32 | 0b010, 3);
+    for &x in literals {
+        assert!(x < 144);
+        bits.push_code(0x30 + x as u32, 8);
+    }
+    if is_damaged {
+        bits.push_code(0xC0 + 286 - 280, 8);
+    }
+    if !(is_damaged && is_final) {
+        bits.push_code(0, 7);
+    }
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{compress, deflate, text};
+use gunzip::producer::{Format, Options, Produce, Producer};
+use gunzip::push::{PushDecoder, Status};
+use gunzip::{Decompressor, Strategy};
+use std::io::{Cursor, Read};
+
+const TAIL: &[u8] = b"the rest of the container, e.g., another gzip member";
+
+/// a gzip member of the data followed by the tail, i.e., a container holding the member
+fn container(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut input = compress(data, 6);
+    let len = input.len();
+    input.extend(TAIL);
+    input.extend(compress(data, 1));
+    (input, len)
+}
+
+/// what follows the stream: the bytes read ahead and not consumed, and then the rest of the reader
+fn rest(mut read: Cursor<Vec<u8>>, mut unconsumed: Vec<u8>) -> Vec<u8> {
+    read.read_to_end(&mut unconsumed).unwrap();
+    unconsumed
+}
+
+#[test]
+fn decompressor_returns_the_rest() {
+    let data = text(300_000, 1);
+    let (input, len) = container(&data);
+    let options = Options {
+        single_member: true,
+        ..Default::default()
+    };
+    let mut decompressor = Decompressor::with_options(Cursor::new(input.clone()), options);
+    let mut out = Vec::new();
+    decompressor.read_to_end(&mut out).unwrap();
+    assert!(out == data);
+    assert_eq!(decompressor.total_in(), Some(len as u64));
+    let (read, unconsumed) = decompressor.into_inner::<Cursor<Vec<u8>>>().unwrap();
+    assert!(rest(read, unconsumed) == input[len..]);
+}
+
+#[test]
+fn only_single_threaded_decompressors_return_the_reader() {
+    let (input, _) = container(&text(1000, 2));
+    let decompressor = Decompressor::builder()
+        .threads(2)
+        .strategy(Strategy::Pipelined)
+        .build(Cursor::new(input.clone()));
+    assert!(decompressor.into_inner::<Cursor<Vec<u8>>>().is_none());
+    let decompressor = Decompressor::new(Cursor::new(input));
+    assert!(decompressor.into_inner::<std::fs::File>().is_none());
+}
+
+#[test]
+fn producer_returns_the_rest() {
+    let data = text(300_000, 3);
+    let (input, len) = container(&data);
+    let options = Options {
+        single_member: true,
+        ..Default::default()
+    };
+    let mut producer = Producer::with_options(Cursor::new(input.clone()), options);
+    let mut out = Vec::new();
+    for produce in producer.by_ref() {
+        match produce {
+            Produce::Data(xs) => out.extend(xs),
+            Produce::End(total_in) => assert_eq!(total_in, len as u64),
+            Produce::Err(e) => panic!("{e}"),
+            _ => {}
+        }
+    }
+    assert!(out == data);
+    let (read, unconsumed) = producer.into_inner();
+    assert!(rest(read, unconsumed) == input[len..]);
+}
+
+#[test]
+fn raw_stream_returns_the_rest() {
+    let data = text(100_000, 4);
+    let mut input = deflate(&data, 6);
+    let len = input.len();
+    input.extend(TAIL);
+    let options = Options {
+        format: Format::Raw,
+        ..Default::default()
+    };
+    let mut producer = Producer::with_options(Cursor::new(input.clone()), options);
+    assert!(producer.any(|produce| matches!(produce, Produce::End(_))));
+    let (read, unconsumed) = producer.into_inner();
+    // the final block ends within the last byte of the stream
+    assert!(rest(read, unconsumed) == input[len..]);
+}
+
+#[test]
+fn push_decoder_returns_the_rest() {
+    let data = text(100_000, 5);
+    let (input, len) = container(&data);
+    let options = Options {
+        single_member: true,
+        ..Default::default()
+    };
+    let mut decoder = PushDecoder::with_options(options);
+    let mut out = Vec::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut chunks = input.chunks(1000);
+    loop {
+        match decoder.pull(&mut buf).unwrap() {
+            Status::Data(n) => out.extend_from_slice(&buf[..n]),
+            Status::NeedInput => decoder.push(chunks.next().unwrap()),
+            Status::End(_) => break,
+            _ => {}
+        }
+    }
+    assert!(out == data);
+    assert_eq!(decoder.total_in(), len as u64);
+    let mut rest = decoder.into_inner();
+    rest.extend(chunks.flatten());
+    assert!(rest == input[len..]);
+}
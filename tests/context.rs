@@ -0,0 +1,84 @@
+mod common;
+
+use common::{compress, fixed_block, text, Bits, GZIP_HEADER, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::{Decompressor, Strategy};
+use std::io::{Cursor, Read};
+
+const MEMBER_LEN: usize = 1 << 20;
+
+/// the error from decompressing with each strategy on 4 threads
+fn errors(gz: &[u8]) -> Vec<Error> {
+    STRATEGIES
+        .into_iter()
+        .map(|strategy| {
+            let mut decompressor = Decompressor::builder()
+                .threads(4)
+                .strategy(strategy)
+                .build(Cursor::new(gz.to_vec()));
+            let e = decompressor.read_to_end(&mut Vec::new()).unwrap_err();
+            *e.into_inner().unwrap().downcast::<Error>().unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn checksum_error_points_at_the_member() {
+    let mut gz = Vec::new();
+    let mut ends = Vec::new();
+    for seed in 0..3 {
+        gz.extend(compress(&text(MEMBER_LEN, seed), 6));
+        ends.push(gz.len());
+    }
+    gz[ends[1] - 8] ^= 1; // CRC32 of the second member
+    for (e, strategy) in errors(&gz).into_iter().zip(STRATEGIES) {
+        assert!(
+            matches!(e.inner(), Error::ChecksumMismatch),
+            "{strategy:?}: {e}"
+        );
+        let context = e.context().unwrap();
+        assert_eq!(context.member, 2, "{strategy:?}");
+        assert_eq!(
+            context.output_position,
+            2 * MEMBER_LEN as u64,
+            "{strategy:?}"
+        );
+        // unknown where the checksum is verified on a thread of its own
+        if !matches!(strategy, Strategy::Pipelined | Strategy::SeparateChecksum) {
+            assert_eq!(
+                context.bit_position,
+                Some(ends[1] as u64 * 8),
+                "{strategy:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn invalid_symbol_points_into_the_block() {
+    let mut gz = compress(&text(MEMBER_LEN, 3), 6);
+    let base = gz.len() + GZIP_HEADER.len();
+    let literals = text(1000, 4);
+    let mut bits = Bits::default();
+    fixed_block(&mut bits, &literals, true, true);
+    gz.extend(GZIP_HEADER);
+    gz.extend(bits.bytes);
+    gz.resize(gz.len() + 100, 0);
+    for (e, strategy) in errors(&gz).into_iter().zip(STRATEGIES) {
+        assert!(
+            matches!(e.inner(), Error::InvalidSymbol),
+            "{strategy:?}: {e}"
+        );
+        let context = e.context().unwrap();
+        assert_eq!(context.member, 2, "{strategy:?}");
+        let output = context.output_position as usize;
+        assert!((MEMBER_LEN..=MEMBER_LEN + literals.len()).contains(&output));
+        // at the symbol, or at the beginning of the block for speculative decompression
+        let bit = context.bit_position.unwrap();
+        let symbol_end = base as u64 * 8 + 3 + 8 * (literals.len() as u64 + 1);
+        assert!(
+            (base as u64 * 8..=symbol_end).contains(&bit),
+            "{strategy:?}"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{compress, decompress, gunzip, random, text};
+
+/// BTYPE of the first deflate block, given a member with a bare 10-byte header
+fn first_block_type(gz: &[u8]) -> u8 {
+    assert_eq!(gz[3], 0, "no optional header fields");
+    gz[10] >> 1 & 0b11
+}
+
+/// 7-bit random bytes mixed with a ladder of rare bytes whose counts double from one to the next,
+/// which rarely match and so stay literals, making the unlimited huffman codes exceed 15 bits
+fn skewed(seed: u64) -> Vec<u8> {
+    let mut data = Vec::new();
+    for chunk in 0..4 {
+        // one ladder per 64kB block
+        let mut ladder = Vec::new();
+        for (i, symbol) in (0x80..0x8eu8).enumerate() {
+            ladder.extend(std::iter::repeat_n(symbol, 1 << i));
+        }
+        let mut xs = random((64 << 10) - ladder.len(), seed + chunk)
+            .into_iter()
+            .map(|x| x & 0x7f)
+            .collect::<Vec<_>>();
+        xs.extend(ladder);
+        // Fisher-Yates shuffle
+        let noise = random(xs.len() * 4, seed + chunk + 100);
+        for i in (1..xs.len()).rev() {
+            let x = u32::from_le_bytes(noise[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+            xs.swap(i, x % (i + 1));
+        }
+        data.extend(xs);
+    }
+    data
+}
+
+fn round_trip(data: &[u8], level: u32) -> Vec<u8> {
+    let gz = compress(data, level);
+    assert_eq!(decompress(&gz).unwrap(), data, "level {level}");
+    if let Some(out) = gunzip(&gz) {
+        assert_eq!(out, data, "level {level} with gzip");
+    }
+    gz
+}
+
+#[test]
+fn empty() {
+    for level in [0, 1, 6, 9] {
+        round_trip(b"", level);
+    }
+}
+
+#[test]
+fn stored_blocks() {
+    // spans several stored blocks of up to 65535 bytes
+    let data = text(200_000, 1);
+    let gz = round_trip(&data, 0);
+    assert_eq!(first_block_type(&gz), 0b00);
+}
+
+#[test]
+fn incompressible_data_is_stored() {
+    let data = random(100_000, 2);
+    for level in [1, 6, 9] {
+        let gz = round_trip(&data, level);
+        assert_eq!(first_block_type(&gz), 0b00, "level {level}");
+        assert!(gz.len() < data.len() + 100);
+    }
+}
+
+#[test]
+fn fixed_block() {
+    let data = b"hello, hello, hello world";
+    for level in [1, 6, 9] {
+        let gz = round_trip(data, level);
+        assert_eq!(first_block_type(&gz), 0b01, "level {level}");
+    }
+}
+
+#[test]
+fn dynamic_blocks() {
+    let data = text(1 << 20, 3);
+    for level in [1, 6, 9] {
+        let gz = round_trip(&data, level);
+        assert_eq!(first_block_type(&gz), 0b10, "level {level}");
+        assert!(gz.len() < data.len() / 3, "level {level}");
+    }
+}
+
+#[test]
+fn length_limited_codes() {
+    let data = skewed(4);
+    for level in [1, 6, 9] {
+        let gz = round_trip(&data, level);
+        assert_eq!(first_block_type(&gz), 0b10, "level {level}");
+    }
+}
+
+#[test]
+fn long_matches_and_runs() {
+    let mut data = vec![0u8; 300_000];
+    data.extend_from_slice(&text(50_000, 5).repeat(4));
+    for level in [1, 6, 9] {
+        round_trip(&data, level);
+    }
+}
+
+#[test]
+fn written_in_small_pieces() {
+    use std::io::Write;
+    let data = text(300_000, 6);
+    for level in [0, 6] {
+        let mut encoder = gunzip::GzEncoder::new(Vec::new(), level);
+        for piece in data.chunks(777) {
+            encoder.write_all(piece).unwrap();
+        }
+        // flushing mid-stream must not break the stream
+        encoder.flush().unwrap();
+        encoder.write_all(b"tail").unwrap();
+        let gz = encoder.finish().unwrap();
+        let mut expected = data.clone();
+        expected.extend_from_slice(b"tail");
+        assert_eq!(decompress(&gz).unwrap(), expected);
+        if let Some(out) = gunzip(&gz) {
+            assert_eq!(out, expected);
+        }
+    }
+}
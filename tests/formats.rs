@@ -0,0 +1,88 @@
+mod common;
+
+use common::{deflate, text, zlib, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::producer::{Format, Options};
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+/// decompress with each strategy on 2 threads
+fn decompress_all(input: &[u8], format: Format) -> Vec<std::io::Result<Vec<u8>>> {
+    let options = Options {
+        format,
+        ..Default::default()
+    };
+    STRATEGIES
+        .into_iter()
+        .map(|strategy| {
+            let mut decompressor = Decompressor::builder()
+                .threads(2)
+                .strategy(strategy)
+                .options(options.clone())
+                .build(Cursor::new(input.to_vec()));
+            let mut out = Vec::new();
+            decompressor.read_to_end(&mut out).map(|_| out)
+        })
+        .collect()
+}
+
+fn inner(e: std::io::Error) -> Error {
+    *e.into_inner().unwrap().downcast::<Error>().unwrap()
+}
+
+#[test]
+fn zlib_streams() {
+    let data = text(200_000, 1);
+    for level in [0, 1, 6] {
+        for out in decompress_all(&zlib(&data, level), Format::Zlib) {
+            assert!(out.unwrap() == data, "{level}");
+        }
+    }
+}
+
+#[test]
+fn zlib_checksum_is_verified() {
+    let mut input = zlib(&text(200_000, 2), 6);
+    *input.last_mut().unwrap() ^= 1;
+    for out in decompress_all(&input, Format::Zlib) {
+        let e = inner(out.unwrap_err());
+        assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+    }
+}
+
+#[test]
+fn zlib_header_is_validated() {
+    let mut input = zlib(&text(1000, 3), 6);
+    input[1] += 1; // FCHECK
+    for out in decompress_all(&input, Format::Zlib) {
+        let e = inner(out.unwrap_err());
+        assert!(matches!(e.inner(), Error::InvalidZlibHeader), "{e}");
+    }
+}
+
+#[test]
+fn raw_streams() {
+    let data = text(200_000, 4);
+    for level in [0, 1, 6] {
+        for out in decompress_all(&deflate(&data, level), Format::Raw) {
+            assert!(out.unwrap() == data, "{level}");
+        }
+    }
+}
+
+#[test]
+fn raw_stream_ends_at_the_final_block() {
+    let data = text(200_000, 5);
+    let raw = deflate(&data, 6);
+    let mut input = raw.clone();
+    input.extend(b"the rest of the container");
+    let options = Options {
+        format: Format::Raw,
+        ..Default::default()
+    };
+    let mut decompressor = Decompressor::with_options(Cursor::new(input), options);
+    let mut out = Vec::new();
+    decompressor.read_to_end(&mut out).unwrap();
+    assert!(out == data);
+    assert_eq!(decompressor.total_in(), Some(raw.len() as u64));
+}
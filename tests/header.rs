@@ -0,0 +1,229 @@
+mod common;
+
+use common::{text, GZIP_HEADER, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::extra_field::{validate, KnownSubfield, Subfield};
+use gunzip::header::{latin1_to_string, Header, HeaderOptions, Os};
+use gunzip::producer::Options;
+use gunzip::{Decompressor, GzEncoder};
+use std::io::{Cursor, Read, Write};
+use std::time::{Duration, SystemTime};
+
+/// gzip member of the data with the header
+fn member(header: Header, data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::with_header(Vec::new(), 6, header);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// header with the name, the modification time and the OS, and a comment in Latin-1
+fn named(name: &str, mtime: u32) -> Header {
+    let mut header = Header::new();
+    header.header[3] = 1; // FTEXT
+    header.header[4..8].copy_from_slice(&mtime.to_le_bytes());
+    header.header[9] = 3; // Unix
+    header.name = Some(name.as_bytes().to_vec());
+    header.comment = Some(b"caf\xe9".to_vec());
+    header
+}
+
+#[test]
+fn header_of_each_member() {
+    let first = text(100_000, 1);
+    let second = text(100_000, 2);
+    let mut gz = member(named("first.txt", 1_700_000_000), &first);
+    gz.extend(member(Header::new(), &second));
+    for strategy in STRATEGIES {
+        let mut decompressor = Decompressor::builder()
+            .threads(2)
+            .strategy(strategy)
+            .build(Cursor::new(gz.clone()));
+        assert!(decompressor.current_header().is_none());
+
+        let mut buf = vec![0; first.len()];
+        decompressor.read_exact(&mut buf).unwrap();
+        let header = decompressor.current_header().unwrap();
+        assert_eq!(header.name(), Some(&b"first.txt"[..]));
+        assert_eq!(latin1_to_string(header.comment().unwrap()), "café");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(header.mtime(), Some(mtime));
+        assert_eq!(header.os(), Os::Unix);
+        assert!(header.is_text());
+
+        decompressor.read_exact(&mut buf[..1]).unwrap();
+        let header = decompressor.current_header().unwrap();
+        assert_eq!(header.name(), None);
+        assert_eq!(header.comment(), None);
+        assert_eq!(header.mtime(), None);
+        assert_eq!(header.os(), Os::Unknown);
+        assert!(!header.is_text());
+    }
+}
+
+/// decompress with each strategy on 2 threads
+fn decompress(gz: &[u8], header: HeaderOptions) -> Vec<std::io::Result<Vec<u8>>> {
+    let options = Options {
+        header,
+        ..Default::default()
+    };
+    STRATEGIES
+        .into_iter()
+        .map(|strategy| {
+            let mut decompressor = Decompressor::builder()
+                .threads(2)
+                .strategy(strategy)
+                .options(options.clone())
+                .build(Cursor::new(gz.to_vec()));
+            let mut out = Vec::new();
+            decompressor.read_to_end(&mut out).map(|_| out)
+        })
+        .collect()
+}
+
+fn inner(e: std::io::Error) -> Error {
+    *e.into_inner().unwrap().downcast::<Error>().unwrap()
+}
+
+#[test]
+fn header_crc16_is_verified() {
+    let data = text(10_000, 3);
+    let mut header = named("crc16.txt", 1_700_000_000);
+    header.crc16 = Some(0); // computed on write
+    let mut gz = member(header, &data);
+    assert_eq!(gz[3] & 2, 2); // FHCRC
+    for out in decompress(&gz, HeaderOptions::default()) {
+        assert!(out.unwrap() == data);
+    }
+
+    gz[4] ^= 1; // MTIME
+    for out in decompress(&gz, HeaderOptions::default()) {
+        let e = inner(out.unwrap_err());
+        assert!(matches!(e.inner(), Error::HeaderChecksumMismatch), "{e}");
+    }
+    let tolerant = HeaderOptions {
+        tolerate_crc16_mismatch: true,
+        ..Default::default()
+    };
+    for out in decompress(&gz, tolerant) {
+        assert!(out.unwrap() == data);
+    }
+}
+
+/// subfield with the id and the data
+fn subfield(id: &[u8; 2], data: &[u8]) -> Vec<u8> {
+    let mut subfield = id.to_vec();
+    subfield.extend((data.len() as u16).to_le_bytes());
+    subfield.extend(data);
+    subfield
+}
+
+#[test]
+fn extra_subfields() {
+    let data = text(10_000, 4);
+    let mut extra_field = subfield(b"BC", &1234u16.to_le_bytes());
+    // version 1, chunk length 58315, 2 chunks of 1000 and 2000 bytes
+    let dictzip = [1, 0, 0xCB, 0xE3, 2, 0, 0xE8, 0x03, 0xD0, 0x07];
+    extra_field.extend(subfield(b"RA", &dictzip));
+    extra_field.extend(subfield(b"zz", b"opaque"));
+    let mut header = Header::new();
+    header.extra_field = Some(extra_field.clone());
+    let gz = member(header, &data);
+
+    let mut decompressor = Decompressor::new(Cursor::new(gz));
+    let mut out = Vec::new();
+    decompressor.read_to_end(&mut out).unwrap();
+    assert!(out == data);
+    let header = decompressor.current_header().unwrap();
+    assert_eq!(header.extra_field.as_deref(), Some(&extra_field[..]));
+    let subfields = header.subfields().unwrap();
+    let known: Vec<_> = subfields.map(|x| x.unwrap().decode().unwrap()).collect();
+    assert_eq!(
+        known,
+        [
+            KnownSubfield::Bgzf { block_size: 1235 },
+            KnownSubfield::Dictzip {
+                version: 1,
+                chunk_len: 58315,
+                chunk_sizes: vec![1000, 2000],
+            },
+            KnownSubfield::Unknown(Subfield {
+                id: *b"zz",
+                data: b"opaque",
+            }),
+        ]
+    );
+    let subfield = header.subfields().unwrap().find_id(*b"RA").unwrap();
+    assert_eq!(subfield.unwrap().data, dictzip);
+}
+
+#[test]
+fn malformed_extra_field() {
+    let mut extra_field = subfield(b"BC", &[0, 0]);
+    extra_field.extend(subfield(b"zz", b"opaque"));
+    assert!(validate(&extra_field).is_ok());
+    let len = extra_field.len();
+    extra_field[len - 7] += 1; // LEN of the last subfield past the end
+    assert!(matches!(
+        validate(&extra_field),
+        Err(Error::InvalidExtraField)
+    ));
+    let mut header = Header::new();
+    header.extra_field = Some(extra_field);
+    let mut decompressor = Decompressor::new(Cursor::new(member(header, b"data")));
+    let mut out = Vec::new();
+    decompressor.read_to_end(&mut out).unwrap();
+    let header = decompressor.current_header().unwrap();
+    let subfields: Vec<_> = header.subfields().unwrap().collect();
+    assert!(matches!(
+        subfields[..],
+        [Ok(_), Err(Error::InvalidExtraField)]
+    ));
+    // the subfields before the malformed one are still found
+    let bgzf = header.subfields().unwrap().find_id(*b"BC").unwrap();
+    assert!(matches!(
+        bgzf.unwrap().decode(),
+        Ok(KnownSubfield::Bgzf { block_size: 1 })
+    ));
+}
+
+#[test]
+fn header_field_limits() {
+    let data = text(10_000, 5);
+    let mut header = named(&"x".repeat(100), 0);
+    header.comment = Some(vec![b'c'; 50]);
+    header.extra_field = Some(subfield(b"zz", &[0; 26]));
+    let gz = member(header, &data);
+    let limits = |name, comment, extra| HeaderOptions {
+        max_name_len: name,
+        max_comment_len: comment,
+        max_extra_len: extra,
+        ..Default::default()
+    };
+    for out in decompress(&gz, limits(100, 50, 30)) {
+        assert!(out.unwrap() == data);
+    }
+    for options in [limits(99, 50, 30), limits(100, 49, 30), limits(100, 50, 29)] {
+        for out in decompress(&gz, options.clone()) {
+            let e = inner(out.unwrap_err());
+            assert!(matches!(e.inner(), Error::HeaderFieldTooLong), "{e}");
+        }
+    }
+}
+
+#[test]
+fn unterminated_name() {
+    let mut gz = GZIP_HEADER.to_vec();
+    gz[3] |= 8; // FNAME
+    let mut long = gz.clone();
+    gz.extend(b"unterminated");
+    long.resize(long.len() + (1 << 20), b'x');
+    for out in decompress(&gz, HeaderOptions::default()) {
+        let e = out.unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof, "{e}");
+    }
+    // the name is not buffered past the limit in search of the terminator
+    for out in decompress(&long, HeaderOptions::default()) {
+        let e = inner(out.unwrap_err());
+        assert!(matches!(e.inner(), Error::HeaderFieldTooLong), "{e}");
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{compress, text};
+use gunzip::error::Error;
+use gunzip::index::{Index, IndexedReader};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+const SPACING: u64 = 128 << 10;
+
+/// 3 members, along with the data
+fn members() -> (Vec<u8>, Vec<u8>) {
+    let mut gz = Vec::new();
+    let mut data = Vec::new();
+    for seed in 0..3 {
+        let part = text(1_000_000, seed);
+        gz.extend(compress(&part, 6));
+        data.extend(part);
+    }
+    (gz, data)
+}
+
+#[test]
+fn index_round_trip() {
+    let (gz, data) = members();
+    let index = Index::build(Cursor::new(&gz), SPACING).unwrap();
+    assert_eq!(index.total_out, data.len() as u64);
+    assert!(index.checkpoints.len() >= 6);
+    for pair in index.checkpoints.windows(2) {
+        assert!(pair[1].out_offset >= pair[0].out_offset + SPACING);
+    }
+
+    let mut buf = Vec::new();
+    index.write(&mut buf).unwrap();
+    let read = Index::read(Cursor::new(&buf)).unwrap();
+    assert_eq!(read.spacing, SPACING);
+    assert_eq!(read.total_out, index.total_out);
+    assert_eq!(read.checkpoints.len(), index.checkpoints.len());
+    for (x, y) in read.checkpoints.iter().zip(&index.checkpoints) {
+        assert_eq!(x.bit_offset, y.bit_offset);
+        assert_eq!(x.out_offset, y.out_offset);
+        assert!(x.window == y.window);
+    }
+
+    buf[0] ^= 1;
+    assert!(matches!(
+        Index::read(Cursor::new(&buf)),
+        Err(Error::InvalidIndex)
+    ));
+}
+
+#[test]
+fn random_access() {
+    let (gz, data) = members();
+    let index = Index::build(Cursor::new(&gz), SPACING).unwrap();
+    let mut reader = IndexedReader::new(Cursor::new(gz), index);
+    let len = data.len() as u64;
+    for pos in [
+        SeekFrom::Start(1_500_000),
+        SeekFrom::Start(10),
+        SeekFrom::End(-5),
+        SeekFrom::Start(999_990), // across the members
+        SeekFrom::Current(300_000),
+        SeekFrom::Start(2_500_000),
+    ] {
+        let offset = reader.seek(pos).unwrap();
+        let expected = match pos {
+            SeekFrom::End(delta) => len.checked_add_signed(delta).unwrap(),
+            _ => offset,
+        };
+        assert_eq!(offset, expected);
+        let mut read = Vec::new();
+        reader.by_ref().take(100).read_to_end(&mut read).unwrap();
+        let end = (offset as usize + 100).min(data.len());
+        assert!(read == data[offset as usize..end], "{pos:?}");
+    }
+}
+
+#[test]
+fn resumes_without_the_preceding_input() {
+    let (mut gz, data) = members();
+    let index = Index::build(Cursor::new(&gz), SPACING).unwrap();
+    let checkpoint = &index.checkpoints[1];
+    let (bit_offset, out_offset) = (checkpoint.bit_offset, checkpoint.out_offset);
+    assert!(out_offset < 1_000_000);
+    // damage the input before the checkpoint, within the first member
+    for x in &mut gz[100..(bit_offset / 8) as usize] {
+        *x = !*x;
+    }
+    let mut reader = IndexedReader::new(Cursor::new(gz), index);
+    reader.seek(SeekFrom::Start(out_offset + 10)).unwrap();
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).unwrap();
+    assert!(out == data[out_offset as usize + 10..]);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::{Bits, GZIP_HEADER, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::producer::{Limits, Options};
+use gunzip::{Decompressor, Strategy};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// keeps track of the peak # bytes allocated
+struct PeakAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: PeakAlloc = PeakAlloc;
+
+/// the tests measuring the peak take turns
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// gzip member of len zeros, in fixed huffman blocks of up to 1MB each,
+/// made of back-references of 258 bytes at distance 1
+fn bomb(len: usize) -> Vec<u8> {
+    let mut bits = Bits::default();
+    let mut left = len;
+    let mut is_first = true;
+    while left > 0 {
+        let n = left.min(1 << 20);
+        left -= n;
+        bits.push((left == 0) as u32 | 0b010, 3);
+        let mut m = n;
+        if is_first {
+            bits.push_code(0x30, 8); // literal 0
+            m -= 1;
+            is_first = false;
+        }
+        while m >= 258 {
+            bits.push_code(0xC0 + 285 - 280, 8); // length 258
+            bits.push_code(0, 5); // distance 1
+            m -= 258;
+        }
+        for _ in 0..m {
+            bits.push_code(0x30, 8);
+        }
+        bits.push_code(0, 7); // end of block
+    }
+    let mut gz = GZIP_HEADER.to_vec();
+    gz.extend(bits.bytes);
+    let crc = crc32fast::hash(&vec![0; len]);
+    gz.extend(crc.to_le_bytes());
+    gz.extend((len as u32).to_le_bytes());
+    gz
+}
+
+/// decompress with the strategy and the limits, returning the peak # bytes allocated meanwhile,
+/// # bytes read and the error
+fn decompress(gz: &[u8], strategy: Strategy, limits: Limits) -> (usize, usize, Option<Error>) {
+    let options = Options {
+        limits,
+        ..Default::default()
+    };
+    let input = gz.to_vec();
+    PEAK.store(ALLOCATED.load(Ordering::Relaxed), Ordering::Relaxed);
+    let base = ALLOCATED.load(Ordering::Relaxed);
+    let mut decompressor = Decompressor::builder()
+        .threads(4)
+        .strategy(strategy)
+        .options(options)
+        .build(Cursor::new(input));
+    let mut buf = vec![0; 1 << 16];
+    let mut n = 0;
+    let e = loop {
+        match decompressor.read(&mut buf) {
+            Ok(0) => break None,
+            Ok(m) => n += m,
+            Err(e) => break Some(e),
+        }
+    };
+    drop(decompressor);
+    let peak = PEAK.load(Ordering::Relaxed).saturating_sub(base);
+    let e = e.map(|e| *e.into_inner().unwrap().downcast::<Error>().unwrap());
+    (peak, n, e)
+}
+
+#[test]
+fn speculative_chunks_stop_at_the_limits() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let gz = bomb(256 << 20);
+    for limits in [
+        Limits {
+            max_output: Some(1 << 20),
+            ..Default::default()
+        },
+        Limits {
+            max_member_output: Some(1 << 20),
+            ..Default::default()
+        },
+        Limits {
+            max_ratio: Some(100),
+            ..Default::default()
+        },
+    ] {
+        let (peak, n, e) = decompress(&gz, Strategy::Speculative, limits);
+        assert!(n <= 1 << 20, "{limits:?}: {n}");
+        assert!(e.is_some(), "{limits:?}");
+        // rather than num_threads chunks of up to 32M symbols
+        assert!(peak < 24 << 20, "{limits:?}: {peak}");
+    }
+}
+
+#[test]
+fn parallel_members_stop_at_the_limit() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let gz = bomb(8 << 20).repeat(16);
+    let limits = Limits {
+        max_output: Some(12 << 20),
+        ..Default::default()
+    };
+    let (peak, n, e) = decompress(&gz, Strategy::ParallelMembers, limits);
+    assert!(n <= 12 << 20, "{n}");
+    assert!(
+        matches!(
+            e.as_ref().map(Error::inner),
+            Some(Error::OutputLimitExceeded)
+        ),
+        "{e:?}"
+    );
+    // rather than 8MB for each of the members in flight
+    assert!(peak < 20 << 20, "{peak}");
+}
+
+#[test]
+fn limits_hold_for_every_strategy() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let gz = bomb(8 << 20).repeat(2);
+    let limits = Limits {
+        max_output: Some(12 << 20),
+        ..Default::default()
+    };
+    for strategy in STRATEGIES {
+        let (_, n, e) = decompress(&gz, strategy, limits);
+        assert!(n <= 12 << 20, "{strategy:?}: {n}");
+        assert!(
+            matches!(
+                e.as_ref().map(Error::inner),
+                Some(Error::OutputLimitExceeded)
+            ),
+            "{strategy:?}: {e:?}"
+        );
+    }
+    let (_, n, e) = decompress(&gz, Strategy::Speculative, Limits::default());
+    assert_eq!(n, 16 << 20);
+    assert!(e.is_none());
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{compress, random, text};
+use gunzip::error::Error;
+use gunzip::{Decompressor, Strategy};
+use std::io::{Cursor, Read};
+
+fn decompress(gz: &[u8], strategy: Strategy, threads: usize) -> std::io::Result<Vec<u8>> {
+    let mut decompressor = Decompressor::builder()
+        .threads(threads)
+        .strategy(strategy)
+        .build(Cursor::new(gz.to_vec()));
+    let mut out = Vec::new();
+    decompressor.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+fn inner(e: std::io::Error) -> Error {
+    *e.into_inner().unwrap().downcast::<Error>().unwrap()
+}
+
+/// text interleaved with incompressible data, for a mix of block types
+fn mixed(len: usize, seed: u64) -> Vec<u8> {
+    let text = text(len, seed);
+    let random = random(len / 4, seed);
+    let mut data = Vec::new();
+    for (x, y) in text.chunks(256 << 10).zip(random.chunks(64 << 10)) {
+        data.extend_from_slice(x);
+        data.extend_from_slice(y);
+    }
+    data
+}
+
+#[test]
+fn speculative_single_member() {
+    // several chunks of compressed input, with matches reaching back across them
+    let data = mixed(6 << 20, 1);
+    let gz = compress(&data, 1);
+    assert!(gz.len() > 2 << 20);
+    for threads in [2, 3, 8] {
+        let out = decompress(&gz, Strategy::Speculative, threads).unwrap();
+        assert!(out == data, "{threads}");
+    }
+}
+
+#[test]
+fn speculative_checksum_is_verified() {
+    let data = mixed(6 << 20, 2);
+    let mut gz = compress(&data, 1);
+    let len = gz.len();
+    gz[len - 8] ^= 1; // CRC32
+    let e = inner(decompress(&gz, Strategy::Speculative, 4).unwrap_err());
+    assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+    gz[len - 8] ^= 1;
+    gz[len - 4] ^= 1; // ISIZE
+    let e = inner(decompress(&gz, Strategy::Speculative, 4).unwrap_err());
+    assert!(matches!(e.inner(), Error::SizeMismatch), "{e}");
+}
+
+#[test]
+fn separate_checksum_is_verified() {
+    let first = text(1 << 20, 3);
+    let mut gz = compress(&first, 6);
+    let mut second = compress(&text(1 << 20, 4), 6);
+    let len = second.len();
+    second[len - 8] ^= 1; // CRC32
+    gz.extend(second);
+    for in_flight in [1, 4] {
+        let mut decompressor = Decompressor::builder()
+            .threads(3)
+            .strategy(Strategy::SeparateChecksum)
+            .in_flight(in_flight)
+            .build(Cursor::new(gz.clone()));
+        let mut out = Vec::new();
+        let e = inner(decompressor.read_to_end(&mut out).unwrap_err());
+        assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+        assert_eq!(e.context().unwrap().member, 2);
+        assert!(out.starts_with(&first));
+    }
+}
+
+#[test]
+fn parallel_members() {
+    let mut gz = Vec::new();
+    let mut data = Vec::new();
+    for (seed, len) in [1000, 300_000, 0, 50_000, 17 << 20, 200_000]
+        .into_iter()
+        .enumerate()
+    {
+        let mut part = text(len, seed as u64);
+        if len == 50_000 {
+            // a false candidate for a member within the stored data
+            part[1000..1010].copy_from_slice(&gz[..10]);
+        }
+        // the large member is too large to buffer, and is inflated on the calling thread
+        let level = if len > 1 << 20 || len == 50_000 { 0 } else { 6 };
+        gz.extend(compress(&part, level));
+        data.extend(part);
+    }
+    for threads in [2, 4] {
+        let out = decompress(&gz, Strategy::ParallelMembers, threads).unwrap();
+        assert!(out == data, "{threads}");
+    }
+}
+
+#[test]
+fn parallel_members_errors_point_at_the_member() {
+    // from a worker, and from the calling thread for the member too large to buffer
+    for len in [100_000, 17 << 20] {
+        let mut gz = Vec::new();
+        for seed in 0..5 {
+            gz.extend(compress(&text(100_000, seed), 6));
+        }
+        let base = gz.len();
+        let mut member = compress(&text(len, 5), if len > 1 << 20 { 0 } else { 6 });
+        let end = member.len();
+        member[end - 8] ^= 1; // CRC32
+        gz.extend(member);
+        gz.extend(compress(&text(100_000, 6), 6));
+
+        let e = inner(decompress(&gz, Strategy::ParallelMembers, 4).unwrap_err());
+        assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+        let context = e.context().unwrap();
+        assert_eq!(context.member, 6);
+        assert_eq!(context.output_position, 500_000 + len as u64);
+        assert_eq!(context.bit_position, Some((base + end) as u64 * 8));
+    }
+}
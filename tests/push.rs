@@ -0,0 +1,138 @@
+mod common;
+
+use common::{compress, fixed_block, text, Bits};
+use gunzip::checksum::{Adler32Checksum, Checksum};
+use gunzip::error::{Error, Result};
+use gunzip::producer::{Format, Options};
+use gunzip::push::{PushDecoder, Status};
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+/// push the input in pieces of chunk_len bytes, pulling all that is decodable after each
+fn push_decode(decoder: &mut PushDecoder, input: &[u8], chunk_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut chunks = input.chunks(chunk_len);
+    loop {
+        match decoder.pull(&mut buf)? {
+            Status::Data(n) => out.extend_from_slice(&buf[..n]),
+            Status::NeedInput => match chunks.next() {
+                Some(chunk) => decoder.push(chunk),
+                None => decoder.finish(),
+            },
+            Status::End(_) => return Ok(out),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn pushed_in_pieces() {
+    let data = text(300_000, 1);
+    let mut gz = compress(&data, 6);
+    gz.extend(compress(&data[..70_000], 0));
+    gz.extend(compress(&data[..50_000], 1));
+    let mut expected = data.clone();
+    expected.extend_from_slice(&data[..70_000]);
+    expected.extend_from_slice(&data[..50_000]);
+    for chunk_len in [1, 7, 4096, gz.len()] {
+        let out = push_decode(&mut PushDecoder::new(), &gz, chunk_len).unwrap();
+        assert!(out == expected, "{chunk_len}");
+    }
+}
+
+#[test]
+fn truncated_input_needs_more() {
+    let gz = compress(&text(100_000, 2), 6);
+    let mut buf = vec![0; 1 << 16];
+    for len in [5, 100, gz.len() / 2, gz.len() - 1] {
+        let mut decoder = PushDecoder::new();
+        decoder.push(&gz[..len]);
+        while !matches!(decoder.pull(&mut buf).unwrap(), Status::NeedInput) {}
+        decoder.finish();
+        let e = loop {
+            match decoder.pull(&mut buf) {
+                Ok(_) => {}
+                Err(e) => break e,
+            }
+        };
+        let Error::StdIoError(e) = e.inner() else {
+            panic!("{len}: {e}");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof, "{len}");
+    }
+}
+
+#[test]
+fn corrupt_final_block_is_not_truncation() {
+    // the invalid symbol is within the last few bits of the input
+    let mut bits = Bits::default();
+    fixed_block(&mut bits, b"hello", true, true);
+    let options = Options {
+        format: Format::Raw,
+        ..Default::default()
+    };
+    for chunk_len in [1, bits.bytes.len()] {
+        let mut decoder = PushDecoder::with_options(options.clone());
+        let e = push_decode(&mut decoder, &bits.bytes, chunk_len).unwrap_err();
+        assert!(matches!(e.inner(), Error::InvalidSymbol), "{e}");
+    }
+}
+
+/// a zlib stream of "say hello", where "hello" is copied from the preset dictionary
+fn zlib_with_dictionary(dictionary: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::default();
+    bits.push(0b011, 3); // final fixed block
+    for &x in b"say " {
+        bits.push_code(0x30 + x as u32, 8);
+    }
+    bits.push_code(259 - 256, 7); // length 5
+    bits.push_code(7, 5); // distance 13 + 2
+    bits.push(2, 2);
+    bits.push_code(0, 7);
+    let mut checksum = Adler32Checksum::new();
+    checksum.update(dictionary);
+    let mut zlib = vec![0x78, 0xBB];
+    zlib.extend(checksum.checksum().to_be_bytes());
+    zlib.extend(bits.bytes);
+    let mut checksum = Adler32Checksum::new();
+    checksum.update(b"say hello");
+    zlib.extend(checksum.checksum().to_be_bytes());
+    zlib
+}
+
+#[test]
+fn preset_dictionary() {
+    let zlib = zlib_with_dictionary(b"hello world");
+    for dictionary in [None, Some(b"hello".to_vec()), Some(b"hello world".to_vec())] {
+        let options = Options {
+            format: Format::Zlib,
+            dictionary: dictionary.clone(),
+            ..Default::default()
+        };
+        let pushed = push_decode(&mut PushDecoder::with_options(options.clone()), &zlib, 1);
+        let mut pulled = Vec::new();
+        let mut decompressor = Decompressor::with_options(Cursor::new(zlib.clone()), options);
+        let pulled = decompressor.read_to_end(&mut pulled).map(|_| pulled);
+        match dictionary.as_deref() {
+            Some(b"hello world") => {
+                assert_eq!(pushed.unwrap(), b"say hello");
+                assert_eq!(pulled.unwrap(), b"say hello");
+            }
+            Some(_) => {
+                assert!(matches!(
+                    pushed.unwrap_err().inner(),
+                    Error::DictionaryMismatch
+                ));
+                assert!(pulled.is_err());
+            }
+            None => {
+                assert!(matches!(
+                    pushed.unwrap_err().inner(),
+                    Error::MissingDictionary
+                ));
+                assert!(pulled.is_err());
+            }
+        }
+    }
+}
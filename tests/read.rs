@@ -0,0 +1,144 @@
+mod common;
+
+use common::{compress, random, text, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::producer::{Produce, Producer};
+use gunzip::{decompress_to_vec, Decompressor};
+use std::io::{BufRead, Cursor, Read};
+
+/// members of text, incompressible data and a short string, for every block type
+fn members() -> (Vec<u8>, Vec<u8>) {
+    let mut gz = Vec::new();
+    let mut data = Vec::new();
+    for part in [text(1 << 20, 1), random(200_000, 2), b"short".to_vec()] {
+        gz.extend(compress(&part, 6));
+        data.extend(part);
+    }
+    (gz, data)
+}
+
+fn read_in_pieces(gz: &[u8], buf_len: usize) -> std::io::Result<Vec<u8>> {
+    let mut decompressor = Decompressor::new(Cursor::new(gz.to_vec()));
+    let mut out = Vec::new();
+    let mut buf = vec![0; buf_len];
+    loop {
+        match decompressor.read(&mut buf)? {
+            0 => return Ok(out),
+            n => out.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[test]
+fn buffers_of_any_size() {
+    let (gz, data) = members();
+    for buf_len in [1, 100, 40_000, 1 << 20, data.len()] {
+        assert!(read_in_pieces(&gz, buf_len).unwrap() == data, "{buf_len}");
+    }
+    assert!(decompress_to_vec(&gz).unwrap() == data);
+}
+
+#[test]
+fn data_read_directly_is_verified() {
+    let (mut gz, data) = members();
+    let len = 1 << 20;
+    let first = compress(&data[..len], 6).len();
+    gz[first - 8] ^= 1; // CRC32 of the first member
+    for buf_len in [100, data.len()] {
+        let e = read_in_pieces(&gz, buf_len).unwrap_err();
+        let e = e.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(
+            matches!(e.inner(), Error::ChecksumMismatch),
+            "{buf_len}: {e}"
+        );
+    }
+    assert!(decompress_to_vec(&gz).is_err());
+}
+
+#[test]
+fn producer_inflates_into_the_buffer() {
+    let data = text(1 << 20, 3);
+    let gz = compress(&data, 6);
+    let mut producer = Producer::new(&gz[..]);
+    assert!(matches!(producer.next(), Some(Produce::Header(_))));
+    let mut out = vec![0; data.len()];
+    let mut len = 0;
+    let mut max_n = 0;
+    loop {
+        let n = producer.read_data(&mut out[len..]).unwrap();
+        if n > 0 {
+            len += n;
+            max_n = max_n.max(n);
+            continue;
+        }
+        match producer.next() {
+            Some(Produce::Data(xs)) => {
+                out[len..len + xs.len()].copy_from_slice(&xs);
+                len += xs.len();
+            }
+            Some(Produce::Footer(_)) => break,
+            _ => panic!("expected data or the footer"),
+        }
+    }
+    assert!(out[..len] == data);
+    // more than the 32kB history kept internally at once
+    assert!(max_n > 32 << 10);
+}
+
+#[test]
+fn lines_of_every_strategy() {
+    let data = text(2 << 20, 4);
+    let mut gz = Vec::new();
+    for part in data.chunks(700_000) {
+        gz.extend(compress(part, 6));
+    }
+    let expected: Vec<&[u8]> = data
+        .split_inclusive(|&x| x == b'\n')
+        .map(|line| line.strip_suffix(b"\n").unwrap_or(line))
+        .collect();
+    for strategy in STRATEGIES {
+        let decompressor = Decompressor::builder()
+            .threads(4)
+            .strategy(strategy)
+            .build(Cursor::new(gz.clone()));
+        let lines: Vec<Vec<u8>> = decompressor.split(b'\n').map(Result::unwrap).collect();
+        assert!(lines == expected, "{strategy:?}");
+    }
+}
+
+#[test]
+fn buffered_and_plain_reads_mix() {
+    let data = text(300_000, 5);
+    let mut decompressor = Decompressor::new(Cursor::new(compress(&data, 6)));
+    let mut out = Vec::new();
+    decompressor.read_until(b'\n', &mut out).unwrap();
+    assert_eq!(out.last(), Some(&b'\n'));
+    let available = decompressor.fill_buf().unwrap().len();
+    assert!(available > 0);
+    decompressor.consume(available / 2);
+    out.extend_from_slice(&data[out.len()..out.len() + available / 2]);
+    decompressor.read_to_end(&mut out).unwrap();
+    assert!(out == data);
+    assert!(decompressor.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn error_after_data_is_not_lost() {
+    let data = text(100_000, 6);
+    let mut gz = compress(&data, 6);
+    let len = gz.len();
+    gz[len - 8] ^= 1; // CRC32
+    let mut decompressor = Decompressor::new(Cursor::new(gz.clone()));
+    let mut buf = vec![0; 2 * data.len()];
+    // the data is returned, and the error is deferred to the next call
+    assert_eq!(decompressor.read(&mut buf).unwrap(), data.len());
+    let e = decompressor.fill_buf().unwrap_err();
+    let e = e.into_inner().unwrap().downcast::<Error>().unwrap();
+    assert!(matches!(e.inner(), Error::ChecksumMismatch), "{e}");
+
+    // nothing follows an error of the producer
+    gz.truncate(len / 2);
+    let mut producer = Producer::new(&gz[..]);
+    assert!(producer.any(|produce| matches!(produce, Produce::Err(_))));
+    assert!(producer.next().is_none());
+}
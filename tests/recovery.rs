@@ -0,0 +1,109 @@
+mod common;
+
+use common::{compress, fixed_block, text, Bits, GZIP_HEADER};
+use gunzip::producer::Options;
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+/// a gzip member with a fixed huffman block of the literals that runs into an invalid symbol
+fn damaged_member(literals: &[u8]) -> Vec<u8> {
+    let mut bits = Bits::default();
+    fixed_block(&mut bits, literals, true, true);
+    let mut gz = GZIP_HEADER.to_vec();
+    gz.extend(bits.bytes);
+    gz
+}
+
+fn recover(gz: &[u8], buf_len: usize) -> (Vec<u8>, Decompressor) {
+    let options = Options {
+        recover: true,
+        ..Default::default()
+    };
+    let mut decompressor = Decompressor::with_options(Cursor::new(gz.to_vec()), options);
+    let mut out = Vec::new();
+    let mut buf = vec![0; buf_len];
+    loop {
+        match decompressor.read(&mut buf).unwrap() {
+            0 => return (out, decompressor),
+            n => out.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[test]
+fn data_before_the_damage_is_delivered() {
+    let literals = text(50_000, 1);
+    let data = text(100_000, 2);
+    let mut gz = damaged_member(&literals);
+    gz.extend(compress(&data, 6));
+    // through the window with small reads, and directly into the buffer with large ones
+    for buf_len in [1000, 1 << 20] {
+        let (out, decompressor) = recover(&gz, buf_len);
+        let mut expected = literals.clone();
+        expected.extend_from_slice(&data);
+        assert!(out == expected, "{buf_len}");
+        // the damage is reported from where the output decoded before it ends
+        let damage = decompressor.damage();
+        assert_eq!(damage.len(), 1, "{buf_len}");
+        let (e, bits) = &damage[0];
+        let context = e.context().unwrap();
+        assert_eq!(context.output_position, literals.len() as u64, "{buf_len}");
+        assert_eq!(
+            bits.start,
+            80 + 3 + 8 * literals.len() as u64 + 8,
+            "{buf_len}"
+        );
+        assert_eq!(
+            bits.end,
+            gz.len() as u64 * 8 - compress(&data, 6).len() as u64 * 8
+        );
+    }
+}
+
+#[test]
+fn undamaged_input_is_unaffected() {
+    let data = text(1 << 20, 3);
+    let gz = compress(&data, 6);
+    let (out, decompressor) = recover(&gz, 1 << 16);
+    assert!(out == data);
+    assert!(decompressor.damage().is_empty());
+}
+
+#[test]
+fn damaged_input_fails_without_recovery() {
+    let mut gz = damaged_member(&text(1000, 4));
+    gz.extend(compress(b"more", 6));
+    let mut out = Vec::new();
+    let e = Decompressor::new(Cursor::new(gz))
+        .read_to_end(&mut out)
+        .unwrap_err();
+    assert!(e.to_string().contains("member 1"), "{e}");
+}
+
+#[test]
+fn resumes_at_a_fixed_block() {
+    let literals = (0..4).map(|i| text(2000, 10 + i)).collect::<Vec<_>>();
+    let data = text(10_000, 5);
+    let mut bits = Bits::default();
+    for (i, literals) in literals.iter().enumerate() {
+        fixed_block(&mut bits, literals, i == 3, i == 1);
+    }
+    let mut gz = GZIP_HEADER.to_vec();
+    gz.extend(bits.bytes);
+    gz.extend([0; 8]); // footer, mismatched due to the damage
+    for next in [vec![], compress(&data, 6)] {
+        let mut gz = gz.clone();
+        gz.extend_from_slice(&next);
+        let (out, decompressor) = recover(&gz, 1 << 16);
+        let mut expected = literals.concat();
+        if !next.is_empty() {
+            expected.extend_from_slice(&data);
+        }
+        assert!(out == expected, "{}", next.len());
+        // resumed at the third block, right past the end of block of the second
+        let damage = decompressor.damage();
+        assert_eq!(damage.len(), 1);
+        let third = 80 + 3 * 2 + 8 * 4000 + 8 + 7 * 2;
+        assert_eq!(damage[0].1.end, third);
+    }
+}
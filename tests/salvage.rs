@@ -0,0 +1,108 @@
+mod common;
+
+use common::{compress, text, GZIP_HEADER, STRATEGIES};
+use gunzip::error::Error;
+use gunzip::producer::Options;
+use gunzip::push::{PushDecoder, Status};
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+/// the output delivered with each strategy before the error
+fn salvage(gz: &[u8]) -> Vec<(Vec<u8>, Error)> {
+    let options = Options {
+        salvage: true,
+        ..Default::default()
+    };
+    STRATEGIES
+        .into_iter()
+        .map(|strategy| {
+            let mut decompressor = Decompressor::builder()
+                .threads(4)
+                .strategy(strategy)
+                .options(options.clone())
+                .build(Cursor::new(gz.to_vec()));
+            let mut out = Vec::new();
+            let mut buf = vec![0; 1 << 16];
+            loop {
+                match decompressor.read(&mut buf) {
+                    Ok(0) => panic!("{strategy:?}: no error"),
+                    Ok(n) => out.extend_from_slice(&buf[..n]),
+                    Err(e) => {
+                        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+                        let e = *e.into_inner().unwrap().downcast::<Error>().unwrap();
+                        return (out, e);
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+fn truncated(e: &Error) -> (u64, u64) {
+    match e.inner() {
+        &Error::Truncated {
+            compressed_offset,
+            output_len,
+        } => (compressed_offset, output_len),
+        _ => panic!("{e}"),
+    }
+}
+
+#[test]
+fn stored_data_is_salvaged_up_to_the_end() {
+    let data = text(200_000, 1);
+    let mut gz = compress(&data[..1000], 6);
+    let base = gz.len() + GZIP_HEADER.len();
+    gz.extend(compress(&data[1000..], 0));
+    // within the first stored block, and within the footer
+    for (len, expected) in [
+        (base + 5 + 12_345, 1000 + 12_345),
+        (gz.len() - 3, data.len()),
+    ] {
+        for (out, e) in salvage(&gz[..len]) {
+            assert!(out == data[..expected], "{len}");
+            assert_eq!(truncated(&e), (len as u64, expected as u64), "{e}");
+        }
+    }
+}
+
+#[test]
+fn compressed_data_is_salvaged() {
+    let data = text(300_000, 2);
+    let gz = compress(&data, 6);
+    for len in [100, gz.len() / 2, gz.len() - 9] {
+        let mut decompressor = Decompressor::new(Cursor::new(gz[..len].to_vec()));
+        let mut strict = Vec::new();
+        decompressor.read_to_end(&mut strict).unwrap_err();
+        for (out, e) in salvage(&gz[..len]) {
+            assert!(out == data[..out.len()], "{len}");
+            assert!(out.len() >= strict.len(), "{len}");
+            assert_eq!(truncated(&e), (len as u64, out.len() as u64), "{e}");
+        }
+    }
+}
+
+#[test]
+fn push_decoder_salvages() {
+    let data = text(200_000, 3);
+    let gz = compress(&data, 0);
+    let len = GZIP_HEADER.len() + 5 + 12_345;
+    let options = Options {
+        salvage: true,
+        ..Default::default()
+    };
+    let mut decoder = PushDecoder::with_options(options);
+    decoder.push(&gz[..len]);
+    decoder.finish();
+    let mut out = Vec::new();
+    let mut buf = vec![0; 1 << 16];
+    let e = loop {
+        match decoder.pull(&mut buf) {
+            Ok(Status::Data(n)) => out.extend_from_slice(&buf[..n]),
+            Ok(_) => {}
+            Err(e) => break e,
+        }
+    };
+    assert!(out == data[..12_345]);
+    assert_eq!(truncated(&e), (len as u64, 12_345), "{e}");
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{compress, text, STRATEGIES};
+use gunzip::producer::{Options, TrailingPolicy};
+use gunzip::push::{PushDecoder, Status};
+use gunzip::Decompressor;
+use std::io::{Cursor, Read};
+
+/// output and the trailing bytes reported
+type Decompressed = (Vec<u8>, Option<(TrailingPolicy, u64)>);
+
+/// decompress with every strategy and with the push decoder
+fn decompress(gz: &[u8], policy: TrailingPolicy) -> Vec<Decompressed> {
+    let options = Options {
+        trailing: policy,
+        ..Default::default()
+    };
+    let mut results = Vec::new();
+    for strategy in STRATEGIES {
+        let mut decompressor = Decompressor::builder()
+            .threads(4)
+            .strategy(strategy)
+            .options(options.clone())
+            .build(Cursor::new(gz.to_vec()));
+        let mut out = Vec::new();
+        decompressor.read_to_end(&mut out).unwrap();
+        results.push((out, decompressor.trailing()));
+    }
+    let mut decoder = PushDecoder::with_options(options);
+    decoder.push(gz);
+    decoder.finish();
+    let mut out = Vec::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut trailing = None;
+    loop {
+        match decoder.pull(&mut buf).unwrap() {
+            Status::Data(n) => out.extend_from_slice(&buf[..n]),
+            Status::Trailing(policy, len) => trailing = Some((policy, len)),
+            Status::End(_) => break,
+            _ => {}
+        }
+    }
+    results.push((out, trailing));
+    results
+}
+
+#[test]
+fn ignore_after_first_member() {
+    let first = text(100_000, 1);
+    let second = compress(&text(100_000, 2), 6);
+    for rest in [
+        second.clone(),
+        vec![0; 100],
+        b"garbage".to_vec(),
+        Vec::new(),
+    ] {
+        let mut gz = compress(&first, 6);
+        gz.extend(&rest);
+        let expected = (!rest.is_empty())
+            .then_some((TrailingPolicy::IgnoreAfterFirstMember, rest.len() as u64));
+        for (out, trailing) in decompress(&gz, TrailingPolicy::IgnoreAfterFirstMember) {
+            assert!(out == first);
+            assert_eq!(trailing, expected);
+        }
+    }
+}
+
+#[test]
+fn other_policies_read_every_member() {
+    let first = text(100_000, 3);
+    let second = text(100_000, 4);
+    let mut gz = compress(&first, 6);
+    gz.extend(compress(&second, 6));
+    gz.extend([0; 100]);
+    let mut expected = first.clone();
+    expected.extend_from_slice(&second);
+    for policy in [TrailingPolicy::IgnoreZeros, TrailingPolicy::IgnoreGarbage] {
+        for (out, trailing) in decompress(&gz, policy) {
+            assert!(out == expected);
+            assert_eq!(trailing, Some((TrailingPolicy::IgnoreZeros, 100)));
+        }
+    }
+}